@@ -0,0 +1,147 @@
+#![no_main]
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use bytebuffer::{ByteReader, Endian};
+use evdev::{AbsoluteAxisCode, EventType, InputEvent, KeyCode};
+use libfuzzer_sys::fuzz_target;
+use pendroid_linux::{
+    backend::{EventSink, FingerBackend, StylusBackend},
+    parse::{Event, MAX_SLOTS},
+    profile::{BarrelConfig, TouchKind},
+};
+
+// Walks the stylus and finger state machines with the events of the input,
+// a length prefixed list of websocket messages, and checks every emitted frame.
+
+const FINGER_TOOLS: [KeyCode; 5] = [
+    KeyCode::BTN_TOOL_FINGER,
+    KeyCode::BTN_TOOL_DOUBLETAP,
+    KeyCode::BTN_TOOL_TRIPLETAP,
+    KeyCode::BTN_TOOL_QUADTAP,
+    KeyCode::BTN_TOOL_QUINTTAP,
+];
+const PEN_TOOLS: [KeyCode; 2] = [KeyCode::BTN_TOOL_PEN, KeyCode::BTN_TOOL_RUBBER];
+
+#[derive(Default)]
+struct DeviceState {
+    keys: HashMap<u16, bool>,
+    slot: Option<i32>,
+    trackings: HashMap<i32, i32>,
+    // A pen tool was released in the current frame
+    pen_released: bool,
+}
+
+impl DeviceState {
+    fn pressed(&self, key: KeyCode) -> bool {
+        self.keys.get(&key.code()).copied().unwrap_or(false)
+    }
+
+    fn apply(&mut self, event: &InputEvent) {
+        if event.event_type() == EventType::KEY {
+            let pressed = event.value() != 0;
+            let was = self.keys.insert(event.code(), pressed).unwrap_or(false);
+            assert_ne!(was, pressed, "unbalanced key {:?}", KeyCode(event.code()));
+            if !pressed && PEN_TOOLS.iter().any(|key| key.code() == event.code()) {
+                self.pen_released = true;
+            }
+        } else if event.event_type() == EventType::ABSOLUTE {
+            if event.code() == AbsoluteAxisCode::ABS_MT_SLOT.0 {
+                assert!((0..MAX_SLOTS as i32).contains(&event.value()));
+                self.slot = Some(event.value());
+            } else if event.code() == AbsoluteAxisCode::ABS_MT_TRACKING_ID.0 {
+                let slot = self.slot.expect("tracking id before any slot");
+                assert!(event.value() >= -1, "invalid tracking id");
+                let old = self.trackings.insert(slot, event.value());
+                assert_ne!(old, Some(event.value()), "repeated tracking id");
+            }
+        }
+    }
+
+    // Checked after each frame
+    fn check_frame(&mut self) {
+        let count = |tools: &[KeyCode]| tools.iter().filter(|key| self.pressed(**key)).count();
+        assert!(count(&FINGER_TOOLS) <= 1, "several finger tools pressed");
+        assert!(count(&PEN_TOOLS) <= 1, "several pen tools pressed");
+        // The pen lifts before its tool leaves proximity
+        assert!(
+            !(self.pen_released && self.pressed(KeyCode::BTN_TOUCH)),
+            "pen tool released while touching"
+        );
+        self.pen_released = false;
+    }
+
+    // Checked after reset
+    fn check_released(&self) {
+        assert!(
+            self.keys.values().all(|pressed| !pressed),
+            "keys left pressed after reset"
+        );
+        assert!(
+            self.trackings.values().all(|id| *id == -1),
+            "contacts left after reset"
+        );
+    }
+}
+
+#[derive(Clone, Default)]
+struct CheckSink(Arc<Mutex<DeviceState>>);
+
+impl EventSink for CheckSink {
+    fn emit(&mut self, events: &[InputEvent]) -> std::io::Result<()> {
+        let mut state = self.0.lock().unwrap();
+        for event in events {
+            state.apply(event);
+        }
+        state.check_frame();
+        Ok(())
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let stylus_state = CheckSink::default();
+    let finger_state = CheckSink::default();
+    let mut stylus = StylusBackend::from_sink(Box::new(stylus_state.clone()));
+    let mut finger = FingerBackend::from_sink(Box::new(finger_state.clone()), TouchKind::Touchpad);
+    let barrel = BarrelConfig::default();
+
+    let mut init = None;
+    let mut input = ByteReader::from_bytes(data);
+    input.set_endian(Endian::LittleEndian);
+    while let Ok(length) = input.read_u16() {
+        let Ok(message) = input.read_bytes(length as usize) else {
+            break;
+        };
+        let mut buf = ByteReader::from_bytes(&message);
+        buf.set_endian(Endian::LittleEndian);
+        for event in Event::parse_message(&mut buf, &mut init) {
+            let Ok((event, _)) = event else {
+                continue;
+            };
+            match event {
+                // Same as a re-initialization of the input backend
+                Event::Init(_) => {
+                    stylus.reset().unwrap();
+                    finger.reset().unwrap();
+                }
+                Event::Stylus(stylus_data) => {
+                    stylus.process(&stylus_data, &barrel).unwrap();
+                    stylus.take_actions();
+                }
+                Event::Finger(finger_data) => finger.process(&[finger_data], None).unwrap(),
+                Event::FingerFrame(frame) => finger
+                    .process(&frame.fingers, Some(frame.timestamp))
+                    .unwrap(),
+                Event::Button(_) | Event::Key(_) => {}
+            }
+        }
+    }
+
+    stylus.reset().unwrap();
+    finger.reset().unwrap();
+    stylus_state.0.lock().unwrap().check_released();
+    finger_state.0.lock().unwrap().check_released();
+});
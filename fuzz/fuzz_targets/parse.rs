@@ -0,0 +1,12 @@
+#![no_main]
+
+use bytebuffer::{ByteReader, Endian};
+use libfuzzer_sys::fuzz_target;
+use pendroid_linux::parse::Event;
+
+// Any websocket message must parse or fail without panicking
+fuzz_target!(|data: &[u8]| {
+    let mut buf = ByteReader::from_bytes(data);
+    buf.set_endian(Endian::LittleEndian);
+    let _ = Event::parse_message(&mut buf, &mut None);
+});
@@ -0,0 +1,108 @@
+use std::{
+    collections::HashMap,
+    io::{Read, Write},
+    net::{SocketAddrV4, TcpStream},
+};
+
+use adb_client::{ADBServer, DeviceShort, RustADBError};
+
+// adb server 와 통신하는 client.
+// tracker 는 이 trait 로만 adb 를 사용하므로, 테스트에서는 가짜 서버로 대체할 수 있습니다.
+
+pub type AdbCallback<'a> = &'a dyn Fn(Vec<DeviceShort>) -> Result<(), RustADBError>;
+
+pub trait AdbClient: Send + Sync {
+    // Call back with the whole device list on connect and on each change,
+    // returns when the connection fails
+    fn track_devices(&self, callback: AdbCallback) -> Result<(), RustADBError>;
+    // Forward the local socket to the remote socket of the device
+    fn forward(&self, serial: &str, local: &str, remote: &str) -> Result<(), RustADBError>;
+    fn remove_forward(&self, serial: &str, local: &str) -> Result<(), RustADBError>;
+}
+
+// Client of an adb server listening on a TCP address
+pub struct AdbServerClient {
+    address: SocketAddrV4,
+}
+
+fn read_message(stream: &mut TcpStream) -> Result<String, RustADBError> {
+    let mut length = [0u8; 4];
+    stream.read_exact(&mut length)?;
+    let length = usize::from_str_radix(&String::from_utf8_lossy(&length), 16)?;
+    let mut message = vec![0u8; length];
+    stream.read_exact(&mut message)?;
+    Ok(String::from_utf8_lossy(&message).to_string())
+}
+
+fn read_status(stream: &mut TcpStream) -> Result<(), RustADBError> {
+    let mut status = [0u8; 4];
+    stream.read_exact(&mut status)?;
+    match &status {
+        b"OKAY" => Ok(()),
+        b"FAIL" => Err(RustADBError::ADBRequestFailed(read_message(stream)?)),
+        status => Err(RustADBError::ADBRequestFailed(format!(
+            "Unexpected status {}",
+            String::from_utf8_lossy(status)
+        ))),
+    }
+}
+
+// Send one host service request and check its status
+fn send_request(stream: &mut TcpStream, service: &str) -> Result<(), RustADBError> {
+    write!(stream, "{:04x}{}", service.len(), service)?;
+    read_status(stream)
+}
+
+impl AdbServerClient {
+    pub fn new(address: SocketAddrV4) -> Self {
+        Self { address }
+    }
+
+    fn connect(&self) -> Result<TcpStream, RustADBError> {
+        // Same as adb_client, a local server is started when it is not running
+        if self.address.ip().is_loopback() {
+            ADBServer::start(&HashMap::new(), &None);
+        }
+        Ok(TcpStream::connect(self.address)?)
+    }
+
+    // Forward service of one device, answered once for the device and once for the result
+    fn device_request(&self, serial: &str, service: &str) -> Result<(), RustADBError> {
+        let mut stream = self.connect()?;
+        send_request(&mut stream, &format!("host-serial:{}:{}", serial, service))?;
+        read_status(&mut stream)
+    }
+}
+
+impl AdbClient for AdbServerClient {
+    fn track_devices(&self, callback: AdbCallback) -> Result<(), RustADBError> {
+        // adb_client calls back per device and skips empty lists, so read the lists here
+        let mut stream = self.connect()?;
+        send_request(&mut stream, "host:track-devices")?;
+        loop {
+            let devices = read_message(&mut stream)?
+                .lines()
+                .filter(|line| !line.is_empty())
+                // A state unknown to adb_client skips the line, not the whole list
+                .filter_map(|line| {
+                    DeviceShort::try_from(line.as_bytes().to_vec())
+                        .inspect_err(|err| {
+                            tracing::warn!("Skipping adb device line {:?}: {}", line, err)
+                        })
+                        .ok()
+                })
+                .collect::<Vec<_>>();
+            callback(devices)?;
+        }
+    }
+
+    // adb_client lists the devices first and fails on any state it does not know,
+    // and it can only remove every forward of a device
+    fn forward(&self, serial: &str, local: &str, remote: &str) -> Result<(), RustADBError> {
+        self.device_request(serial, &format!("forward:{};{}", local, remote))
+    }
+
+    fn remove_forward(&self, serial: &str, local: &str) -> Result<(), RustADBError> {
+        self.device_request(serial, &format!("killforward:{}", local))
+    }
+}
@@ -1,24 +1,43 @@
-use std::sync::Arc;
+use std::{
+    collections::HashMap,
+    mem::discriminant,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+};
 
-use adb_client::{ADBServer, DeviceShort, DeviceState, RustADBError};
+use adb_client::{DeviceShort, DeviceState, RustADBError};
+use notify_rust::Notification;
 use qwreey_utility_rs::RwMap;
 use tokio::task::JoinHandle;
 
 use crate::{
     DeviceMap, WorkerIdMap,
+    adb_client::AdbClient,
+    backoff::{Backoff, BackoffConfig, LinkStatus, Status},
     cli::{DeviceList, DeviceListUtil},
     connect_ws::connect_ws,
 };
 
+const REMOTE: &str = "tcp:23227";
+
 fn connected(userdata: &Arc<RwMap>, device: DeviceShort, port: i32) -> Result<(), RustADBError> {
     tracing::info!("Device connected: {}", device.identifier.as_str());
 
     // Forward server to local
-    ADBServer::default()
-        .get_device_by_name(device.identifier.as_str())?
-        .forward(String::from("tcp:23227"), format!("tcp:{}", port))?;
+    let client = userdata.get_of::<Arc<dyn AdbClient>>().unwrap().clone();
+    client.forward(&device.identifier, &format!("tcp:{}", port), REMOTE)?;
 
-    userdata.get_mut::<DeviceMap>("device_map").unwrap().insert(
+    // A running worker reconnects through the new forward by itself
+    let mut map = userdata.get_mut::<DeviceMap>("device_map").unwrap();
+    if map
+        .get(&device.identifier)
+        .is_some_and(|task| !task.is_finished())
+    {
+        return Ok(());
+    }
+    map.insert(
         device.identifier.clone(),
         tokio::spawn(connect_ws(userdata.to_owned(), port, device)),
     );
@@ -26,15 +45,27 @@ fn connected(userdata: &Arc<RwMap>, device: DeviceShort, port: i32) -> Result<()
     Ok(())
 }
 
-fn disconnected(userdata: &Arc<RwMap>, device: DeviceShort) {
+fn disconnected(userdata: &Arc<RwMap>, serial: &str, port: i32) {
+    // The forward may already be gone with the device
+    let client = userdata.get_of::<Arc<dyn AdbClient>>().unwrap().clone();
+    if let Err(err) = client.remove_forward(serial, &format!("tcp:{}", port)) {
+        tracing::debug!("Failed to remove forward of {}: {}", serial, err);
+    }
+
     let mut map = userdata.get_mut::<DeviceMap>("device_map").unwrap();
 
     userdata
         .get_mut::<WorkerIdMap>("worker_id_map")
         .unwrap()
-        .remove(&device.identifier);
+        .remove(serial);
+    // After the worker id, a stopping worker no longer writes its status
+    userdata
+        .get_of_mut::<Status>()
+        .unwrap()
+        .devices
+        .remove(serial);
 
-    if let Some(task) = map.remove(device.identifier.as_str())
+    if let Some(task) = map.remove(serial)
         && !task.is_finished()
     {
         tokio::spawn(async move {
@@ -44,32 +75,133 @@ fn disconnected(userdata: &Arc<RwMap>, device: DeviceShort) {
     }
 }
 
-fn reset(userdata: &Arc<RwMap>) {
-    userdata.get_mut::<DeviceMap>("device_map").unwrap().clear();
+// Start a worker for every device, reached on its port without adb
+pub fn connect_direct(userdata: &Arc<RwMap>) {
+    let list = userdata.get_of::<DeviceList>().unwrap().clone();
+    let mut map = userdata.get_mut::<DeviceMap>("device_map").unwrap();
+    for device in list {
+        tracing::info!("Connecting to {} without adb", device.name);
+        let short = DeviceShort {
+            identifier: device.name.clone(),
+            state: DeviceState::Device,
+        };
+        map.insert(
+            device.name,
+            tokio::spawn(connect_ws(userdata.to_owned(), device.bind_port, short)),
+        );
+    }
+}
+
+fn notify_unauthorized(serial: &str) {
+    let serial = serial.to_string();
+    tokio::spawn(async move {
+        let notification = Notification::new()
+            .summary("Pendroid Wired Unauthorized")
+            .body(
+                format!(
+                    "Accept the USB debugging (RSA key) prompt on {} to connect",
+                    &serial
+                )
+                .as_str(),
+            )
+            .appname("Pendroid Linux")
+            // Unlike the connection notices this asks for an action on the tablet,
+            // so it stays for 10 seconds (milliseconds, as in the others)
+            .timeout(10000)
+            .show_async()
+            .await;
+        if let Err(err) = notification {
+            tracing::error!("Error while displaying notification: {}", err);
+        }
+    });
+}
+
+// Last listed state of every configured device
+type KnownStates = HashMap<String, DeviceState>;
+
+// Act on the state changes between the last device list and this one
+fn update_devices(
+    userdata: &Arc<RwMap>,
+    known: &mut KnownStates,
+    fresh: bool,
+    devices: Vec<DeviceShort>,
+) -> Result<(), RustADBError> {
+    let list = userdata.get_of::<DeviceList>().unwrap().clone();
+    let mut previous = std::mem::take(known);
+
+    for device in devices {
+        let Some(port) = list.get_port(&device.identifier) else {
+            continue;
+        };
+        let last = previous.remove(&device.identifier);
+        known.insert(device.identifier.clone(), device.state.clone());
+        // Forwards do not survive an adb server restart, so a new session applies every state
+        if !fresh
+            && last
+                .as_ref()
+                .is_some_and(|last| discriminant(last) == discriminant(&device.state))
+        {
+            continue;
+        }
+
+        match device.state {
+            DeviceState::Device => connected(userdata, device, port)?,
+            ref state => {
+                tracing::info!("Device {} is {}", device.identifier, state);
+                // Unknown before this list, a worker may be left from the last session
+                if last.is_none_or(|last| matches!(last, DeviceState::Device)) {
+                    disconnected(userdata, &device.identifier, port);
+                }
+                if matches!(state, DeviceState::Unauthorized) {
+                    notify_unauthorized(&device.identifier);
+                }
+            }
+        }
+    }
+
+    // Gone from the list, unplugged or killed
+    for (serial, state) in previous {
+        if let Some(port) = list.get_port(&serial) {
+            tracing::info!("Device removed: {} (was {})", serial, state);
+            disconnected(userdata, &serial, port);
+        }
+    }
+    Ok(())
 }
 
 pub fn run_adb_tracker(userdata: Arc<RwMap>) -> JoinHandle<()> {
     tokio::task::spawn_blocking(move || {
+        let mut backoff = Backoff::new(*userdata.get_of::<BackoffConfig>().unwrap());
+        // Kept across sessions, so devices gone while adb was down are disconnected
+        let known = Mutex::new(KnownStates::new());
         loop {
-            let userdata_clone = userdata.clone();
-            reset(&userdata);
-            let tracking = ADBServer::default().track_devices(move |device| {
-                let state = device.state.clone() as i32;
-
-                let list = userdata_clone.get_of::<DeviceList>().unwrap();
-                if let Some(port) = list.get_port(&device.identifier) {
-                    if state == DeviceState::Device as i32 {
-                        connected(&userdata_clone, device, port)?;
-                    } else if state == DeviceState::Offline as i32 {
-                        tracing::info!("Device disconnected: {}", device.identifier.as_str());
-                        disconnected(&userdata_clone, device);
-                    }
+            let client = userdata.get_of::<Arc<dyn AdbClient>>().unwrap().clone();
+            // Set by the first device list, the server is reachable
+            let tracked = AtomicBool::new(false);
+            let tracking = client.track_devices(&|devices| {
+                let fresh = !tracked.swap(true, Ordering::Relaxed);
+                if fresh {
+                    userdata.get_of_mut::<Status>().unwrap().adb = Some(LinkStatus::Connected);
                 }
-                Ok(())
+                update_devices(&userdata, &mut known.lock().unwrap(), fresh, devices)
             });
             if let Err(err) = tracking {
                 tracing::error!("Error while tracking devices: {}", err);
             }
+
+            if tracked.load(Ordering::Relaxed) {
+                backoff.reset();
+            }
+            let attempt = backoff.attempt() + 1;
+            let delay = backoff.next_delay();
+            tracing::info!(
+                "Retrying adb in {:.1}s (attempt {})",
+                delay.as_secs_f32(),
+                attempt
+            );
+            userdata.get_of_mut::<Status>().unwrap().adb =
+                Some(LinkStatus::retrying(delay, attempt));
+            std::thread::sleep(delay);
         }
     })
 }
@@ -1,14 +1,20 @@
-use crate::backend::BackendConfig;
+use crate::{
+    backend::{BackendConfig, DeviceIdentity},
+    profile::TouchKind,
+};
 
 use super::{
-    super::super::parse::{Finger, Init},
+    super::super::parse::{Finger, Init, MAX_SLOTS},
     event_list::PushEvent,
+    merge::Arbitrated,
+    naming::{device_name, device_phys},
+    sink::EventSink,
     with_abs::WithAbs,
 };
 
 use evdev::{
-    AbsInfo, AbsoluteAxisCode, AttributeSet, BusType, InputEvent, InputId, KeyCode, PropType,
-    UinputAbsSetup, uinput::VirtualDevice,
+    AbsInfo, AbsoluteAxisCode, AttributeSet, BusType, InputEvent, InputId, KeyCode, MiscCode,
+    PropType, UinputAbsSetup, uinput::VirtualDevice,
 };
 use qwreey_utility_rs::ErrToString;
 
@@ -27,23 +33,62 @@ const TOUCHS: [KeyCode; 5] = [
 ];
 
 pub struct FingerBackend {
-    device: VirtualDevice,
+    device: Box<dyn EventSink>,
+    kind: TouchKind,
     current_slot: i32,
     current_touching: bool,
     current_count: i32,
     inputs: Vec<InputEvent>,
-    touch_trackings: [i32; 12],
-    touch_active: [bool; 12],
+    touch_trackings: [i32; MAX_SLOTS as usize],
+    touch_active: [bool; MAX_SLOTS as usize],
+}
+
+impl Arbitrated for FingerBackend {
+    fn in_proximity(&self) -> bool {
+        self.current_count != 0 || self.touch_trackings.iter().any(|id| *id != -1)
+    }
 }
 
 impl FingerBackend {
     // TODO: custumizable fuzz, flat, resolution variable by command line arguments
     // Create new evdev device
-    pub fn new(config: &BackendConfig, init_data: &Init) -> Result<Self, String> {
+    pub fn new(
+        config: &BackendConfig,
+        identity: &DeviceIdentity,
+        init_data: &Init,
+        kind: TouchKind,
+    ) -> Result<Self, String> {
+        // Touchscreen maps the contacts directly onto the screen
+        let (kind_name, product_id, property) = match kind {
+            TouchKind::Touchpad => (
+                "touchpad",
+                config.evdev_trackpad_product_id,
+                PropType::POINTER,
+            ),
+            TouchKind::Touchscreen => (
+                "touchscreen",
+                config.evdev_touchscreen_product_id,
+                PropType::DIRECT,
+            ),
+        };
+        let name = device_name(kind_name, identity);
+        // Resolution from the physical size, or the command line when it is unknown
+        let (res_x, res_y) = if init_data.width_mm != 0 && init_data.height_mm != 0 {
+            init_data.resolution()
+        } else {
+            (config.evdev_trackpad_res, config.evdev_trackpad_res)
+        };
         let mut device = VirtualDevice::builder()
             .err_to_string()?
-            .name("pendroid-touchpad")
-            .input_id(InputId::new(BusType::BUS_USB, 0u16, 1333u16, 1u16))
+            .name(&name)
+            .input_id(InputId::new(
+                BusType::BUS_USB,
+                config.evdev_vendor_id,
+                product_id,
+                1u16,
+            ))
+            .with_phys(&device_phys(kind_name, identity)?)
+            .err_to_string()?
             .with_abs(&[
                 // TOOL INFO
                 UinputAbsSetup::new(
@@ -59,7 +104,7 @@ impl FingerBackend {
                         init_data.width as i32,
                         config.evdev_trackpad_fuzz,
                         config.evdev_trackpad_flat,
-                        config.evdev_trackpad_res,
+                        res_x,
                     ),
                     // AbsInfo::new(0, 0, init_data.width as i32, 0, 0, 11),
                 ),
@@ -71,7 +116,7 @@ impl FingerBackend {
                         init_data.height as i32,
                         config.evdev_trackpad_fuzz,
                         config.evdev_trackpad_flat,
-                        config.evdev_trackpad_res,
+                        res_y,
                     ),
                     // AbsInfo::new(0, 0, init_data.height as i32, 0, 0, 11),
                 ),
@@ -85,7 +130,7 @@ impl FingerBackend {
                         init_data.width as i32,
                         config.evdev_trackpad_fuzz,
                         config.evdev_trackpad_flat,
-                        config.evdev_trackpad_res,
+                        res_x,
                     ),
                 ),
                 UinputAbsSetup::new(
@@ -97,13 +142,13 @@ impl FingerBackend {
                         init_data.height as i32,
                         config.evdev_trackpad_fuzz,
                         config.evdev_trackpad_flat,
-                        config.evdev_trackpad_res,
+                        res_y,
                     ),
                 ),
                 // ABS SLOT
                 UinputAbsSetup::new(
                     AbsoluteAxisCode::ABS_MT_SLOT,
-                    AbsInfo::new(0, 0, 12, 0, 0, 1),
+                    AbsInfo::new(0, 0, MAX_SLOTS as i32 - 1, 0, 0, 1),
                 ),
                 // ABS_MT_TRACKING_ID
                 UinputAbsSetup::new(
@@ -121,8 +166,10 @@ impl FingerBackend {
                 // KeyCode::BTN_LEFT,
             ]))
             .err_to_string()?
+            .with_msc(&AttributeSet::from_iter([MiscCode::MSC_TIMESTAMP]))
+            .err_to_string()?
             .with_properties(&AttributeSet::from_iter([
-                PropType::POINTER,
+                property,
                 // PropType::BUTTONPAD,
             ]))
             .err_to_string()?
@@ -134,15 +181,25 @@ impl FingerBackend {
             tracing::info!("New finger backend available as {}", path.display());
         }
 
-        Ok(Self {
+        Ok(Self::from_sink(Box::new(device), kind))
+    }
+
+    // Backend writing to any sink instead of a uinput device
+    pub fn from_sink(device: Box<dyn EventSink>, kind: TouchKind) -> Self {
+        Self {
             device,
+            kind,
             inputs: Vec::<InputEvent>::with_capacity(32),
             current_slot: -1,
             current_touching: false,
             current_count: 0,
-            touch_active: [false; 12],
-            touch_trackings: [-1i32; 12],
-        })
+            touch_active: [false; MAX_SLOTS as usize],
+            touch_trackings: [-1i32; MAX_SLOTS as usize],
+        }
+    }
+
+    pub fn kind(&self) -> TouchKind {
+        self.kind
     }
 
     // Update slot
@@ -155,35 +212,16 @@ impl FingerBackend {
         }
     }
 
-    pub fn process(&mut self, touch_data: &Finger) -> Result<(), String> {
+    // Emit the changed slots with a single SYN_REPORT
+    pub fn process(&mut self, fingers: &[Finger], timestamp: Option<i32>) -> Result<(), String> {
         self.inputs.clear();
-        let index = touch_data.slot as usize;
-        let x = touch_data.x as i32;
-        let y = touch_data.y as i32;
-
-        // Update ABS_MT_TRACKING_ID
-        if self.touch_trackings[index] != touch_data.tracking_id {
-            self.update_slot(touch_data.slot);
-            self.touch_trackings[index] = touch_data.tracking_id;
-            self.inputs
-                .push_abs_event(ABS_MT_TRACKING_ID, touch_data.tracking_id);
+        for touch_data in fingers {
+            self.update(touch_data);
         }
 
-        // Update ABS_MT_POSITION
-        if touch_data.x != -1 && touch_data.y != -1 {
-            self.update_slot(touch_data.slot);
-            self.inputs.push_abs_event(ABS_MT_POSITION_X, x);
-            self.inputs.push_abs_event(ABS_MT_POSITION_Y, y);
-        }
-
-        // Count touch
-        self.touch_active[index] = touch_data.down;
-        let mut count = 0;
-        for active in self.touch_active {
-            if active {
-                count += 1;
-            }
-        }
+        // Count touch, the tool keys go up to five fingers
+        let count = self.touch_active.iter().filter(|active| **active).count() as i32;
+        let count = count.min(TOUCHS.len() as i32);
 
         // Touch event (BTN_TOUCH)
         let touching = count != 0;
@@ -204,14 +242,178 @@ impl FingerBackend {
         }
         self.current_count = count;
 
+        // MSC_TIMESTAMP is microseconds
+        if let Some(timestamp) = timestamp {
+            self.inputs
+                .push_msc(MiscCode::MSC_TIMESTAMP.0, timestamp.wrapping_mul(1000));
+        }
+        self.device.emit(self.inputs.as_slice()).err_to_string()?;
+        Ok(())
+    }
+
+    // Push the slot events of one finger
+    fn update(&mut self, touch_data: &Finger) {
+        let index = touch_data.slot as usize;
+        let x = touch_data.x as i32;
+        let y = touch_data.y as i32;
+
+        // Update ABS_MT_TRACKING_ID
+        if self.touch_trackings[index] != touch_data.tracking_id {
+            self.update_slot(touch_data.slot);
+            self.touch_trackings[index] = touch_data.tracking_id;
+            self.inputs
+                .push_abs_event(ABS_MT_TRACKING_ID, touch_data.tracking_id);
+        }
+
+        // Update ABS_MT_POSITION
+        if touch_data.x != -1 && touch_data.y != -1 {
+            self.update_slot(touch_data.slot);
+            self.inputs.push_abs_event(ABS_MT_POSITION_X, x);
+            self.inputs.push_abs_event(ABS_MT_POSITION_Y, y);
+        }
+
+        self.touch_active[index] = touch_data.down;
+
         // Update XY
         if touch_data.x != -1 && touch_data.y != -1 && touch_data.slot == 0 {
             self.update_slot(touch_data.slot);
             self.inputs.push_abs_event(ABS_X, x);
             self.inputs.push_abs_event(ABS_Y, y);
         }
+    }
 
-        self.device.emit(self.inputs.as_slice()).err_to_string()?;
+    // Release every active slot and touch key
+    pub fn reset(&mut self) -> Result<(), String> {
+        self.inputs.clear();
+
+        for index in 0..self.touch_trackings.len() {
+            if self.touch_trackings[index] != -1 {
+                self.update_slot(index as u8);
+                self.inputs.push_abs_event(ABS_MT_TRACKING_ID, -1);
+            }
+        }
+        if self.current_touching {
+            self.inputs.push_key(&KeyCode::BTN_TOUCH, 0);
+        }
+        if self.current_count != 0 {
+            self.inputs
+                .push_key(&TOUCHS[self.current_count as usize - 1], 0);
+        }
+
+        self.current_touching = false;
+        self.current_count = 0;
+        self.touch_active = [false; MAX_SLOTS as usize];
+        self.touch_trackings = [-1i32; MAX_SLOTS as usize];
+
+        if !self.inputs.is_empty() {
+            self.device.emit(self.inputs.as_slice()).err_to_string()?;
+        }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::evdev::sink::CaptureSink;
+    use evdev::EventType;
+
+    // Key events of every emitted frame
+    type KeyFrames = Vec<Vec<(KeyCode, i32)>>;
+
+    const TOUCH: KeyCode = KeyCode::BTN_TOUCH;
+
+    fn finger(slot: u8, down: bool) -> Finger {
+        Finger {
+            slot,
+            down,
+            total_down: 0,
+            tracking_id: if down { slot as i32 + 1 } else { -1 },
+            x: 10,
+            y: 10,
+        }
+    }
+
+    // Fingers down in the slots below `count`
+    fn fingers(count: u8) -> Vec<Finger> {
+        (0..count).map(|slot| finger(slot, true)).collect()
+    }
+
+    fn keys(frames: Vec<Vec<InputEvent>>) -> KeyFrames {
+        frames
+            .iter()
+            .map(|frame| {
+                frame
+                    .iter()
+                    .filter(|event| event.event_type() == EventType::KEY)
+                    .map(|event| (KeyCode::new(event.code()), event.value()))
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn finger_counting() {
+        let [one, two, three, _, five] = TOUCHS;
+        let cases: &[(&str, Vec<Vec<Finger>>, KeyFrames)] = &[
+            (
+                "one finger",
+                vec![fingers(1)],
+                vec![vec![(TOUCH, 1), (one, 1)]],
+            ),
+            (
+                "two fingers in one frame",
+                vec![fingers(2)],
+                vec![vec![(TOUCH, 1), (two, 1)]],
+            ),
+            (
+                "second finger joins",
+                vec![fingers(1), fingers(2)],
+                vec![vec![(TOUCH, 1), (one, 1)], vec![(one, 0), (two, 1)]],
+            ),
+            (
+                "one of three lifts",
+                vec![fingers(3), vec![finger(2, false)]],
+                vec![vec![(TOUCH, 1), (three, 1)], vec![(three, 0), (two, 1)]],
+            ),
+            (
+                "more than five fingers",
+                vec![fingers(5), fingers(7)],
+                vec![vec![(TOUCH, 1), (five, 1)], vec![]],
+            ),
+            (
+                "every finger lifts",
+                vec![fingers(2), vec![finger(0, false), finger(1, false)]],
+                vec![vec![(TOUCH, 1), (two, 1)], vec![(TOUCH, 0), (two, 0)]],
+            ),
+        ];
+
+        for (name, frames, expected) in cases {
+            let sink = CaptureSink::new();
+            let mut backend = FingerBackend::from_sink(Box::new(sink.clone()), TouchKind::Touchpad);
+            for frame in frames {
+                backend.process(frame, None).unwrap();
+            }
+            assert_eq!(&keys(sink.take()), expected, "{name}");
+        }
+    }
+
+    #[test]
+    fn reset_releases_slots() {
+        let sink = CaptureSink::new();
+        let mut backend = FingerBackend::from_sink(Box::new(sink.clone()), TouchKind::Touchpad);
+        backend.process(&fingers(3), None).unwrap();
+        sink.take();
+
+        backend.reset().unwrap();
+        let frames = sink.take();
+        assert_eq!(frames.len(), 1);
+        let released = frames[0]
+            .iter()
+            .filter(|event| event.code() == ABS_MT_TRACKING_ID && event.value() == -1)
+            .count();
+        assert_eq!(released, 3);
+        assert_eq!(keys(frames), vec![vec![(TOUCH, 0), (TOUCHS[2], 0)]]);
+        assert!(!backend.in_proximity());
+    }
+}
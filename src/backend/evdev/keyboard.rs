@@ -0,0 +1,115 @@
+use crate::backend::{BackendConfig, DeviceIdentity};
+
+use super::{
+    event_list::{EventList, PushEvent},
+    naming::{device_name, device_phys},
+};
+
+use evdev::{
+    AttributeSet, BusType, EventType, InputEvent, InputId, KeyCode, RelativeAxisCode,
+    uinput::VirtualDevice,
+};
+use qwreey_utility_rs::ErrToString;
+
+// 단축키, 클릭 등의 동작을 내보내는 키보드 / 포인터 장치
+
+const KEY_MAX: u16 = KeyCode::KEY_MICMUTE.0;
+
+pub struct KeyboardBackend {
+    device: VirtualDevice,
+    inputs: EventList,
+}
+
+impl KeyboardBackend {
+    // Create new evdev device
+    pub fn new(config: &BackendConfig, identity: &DeviceIdentity) -> Result<Self, String> {
+        let name = device_name("keyboard", identity);
+        let mut keys = AttributeSet::from_iter((1..=KEY_MAX).map(KeyCode::new));
+        keys.insert(KeyCode::BTN_LEFT);
+        keys.insert(KeyCode::BTN_RIGHT);
+        keys.insert(KeyCode::BTN_MIDDLE);
+
+        let mut device = VirtualDevice::builder()
+            .err_to_string()?
+            .name(&name)
+            .input_id(InputId::new(
+                BusType::BUS_USB,
+                config.evdev_vendor_id,
+                config.evdev_keyboard_product_id,
+                1u16,
+            ))
+            .with_phys(&device_phys("keyboard", identity)?)
+            .err_to_string()?
+            .with_keys(&keys)
+            .err_to_string()?
+            // Pointer axes, so that mouse buttons are accepted by libinput
+            .with_relative_axes(&AttributeSet::from_iter([
+                RelativeAxisCode::REL_X,
+                RelativeAxisCode::REL_Y,
+                RelativeAxisCode::REL_WHEEL,
+                RelativeAxisCode::REL_HWHEEL,
+            ]))
+            .err_to_string()?
+            .build()
+            .err_to_string()?;
+
+        for path in device.enumerate_dev_nodes_blocking().err_to_string()? {
+            let path = path.err_to_string()?;
+            tracing::info!("New keyboard backend available as {}", path.display());
+        }
+
+        Ok(Self {
+            device,
+            inputs: Vec::<InputEvent>::with_capacity(16),
+        })
+    }
+
+    // Press keys in order, release them in reverse order
+    pub fn chord(&mut self, keys: &[KeyCode], pressed: bool) -> Result<(), String> {
+        self.inputs.clear();
+        if pressed {
+            for key in keys {
+                self.inputs.push_key(key, 1);
+            }
+        } else {
+            for key in keys.iter().rev() {
+                self.inputs.push_key(key, 0);
+            }
+        }
+        self.device.emit(&self.inputs).err_to_string()
+    }
+
+    pub fn tap(&mut self, keys: &[KeyCode]) -> Result<(), String> {
+        self.chord(keys, true)?;
+        self.chord(keys, false)
+    }
+
+    // Scroll wheel steps while holding keys (e.g. ctrl + wheel for zooming)
+    pub fn scroll(&mut self, keys: &[KeyCode], x: i32, y: i32) -> Result<(), String> {
+        if !keys.is_empty() {
+            self.chord(keys, true)?;
+        }
+
+        self.inputs.clear();
+        if x != 0 {
+            self.inputs.push(InputEvent::new(
+                EventType::RELATIVE.0,
+                RelativeAxisCode::REL_HWHEEL.0,
+                x,
+            ));
+        }
+        if y != 0 {
+            self.inputs.push(InputEvent::new(
+                EventType::RELATIVE.0,
+                RelativeAxisCode::REL_WHEEL.0,
+                y,
+            ));
+        }
+        self.device.emit(&self.inputs).err_to_string()?;
+
+        if !keys.is_empty() {
+            self.chord(keys, false)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,236 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, Weak},
+};
+
+use super::{finger::FingerBackend, stylus::StylusBackend};
+
+// 여러 태블릿이 하나의 가상 장치를 공유할 수 있도록 합니다.
+// 공유 장치는 현재 사용중 (proximity) 인 기기만 입력할 수 있습니다.
+
+pub trait Arbitrated {
+    // Whether the device is still used (pen in proximity, finger on surface)
+    fn in_proximity(&self) -> bool;
+}
+
+pub struct Merged<T> {
+    backend: T,
+    owner: Option<String>,
+}
+
+pub type SharedBackend<T> = Arc<Mutex<Merged<T>>>;
+
+pub enum BackendLink<T> {
+    Owned(T),
+    Merged {
+        serial: String,
+        shared: SharedBackend<T>,
+    },
+}
+
+impl<T: Arbitrated> BackendLink<T> {
+    pub fn merged(serial: &str, shared: SharedBackend<T>) -> Self {
+        Self::Merged {
+            serial: serial.to_string(),
+            shared,
+        }
+    }
+
+    pub fn is_merged(&self) -> bool {
+        matches!(self, Self::Merged { .. })
+    }
+
+    // Run f on the backend unless another device owns the shared backend
+    pub fn with(&mut self, f: impl FnOnce(&mut T) -> Result<(), String>) -> Result<(), String> {
+        match self {
+            Self::Owned(backend) => f(backend),
+            Self::Merged { serial, shared } => {
+                let mut merged = shared.lock().unwrap();
+                if merged.owner.as_ref().is_some_and(|owner| owner != serial) {
+                    return Ok(());
+                }
+                let result = f(&mut merged.backend);
+                merged.owner = merged.backend.in_proximity().then(|| serial.clone());
+                result
+            }
+        }
+    }
+
+    // Run the reset routine f unless the shared backend is used by another device
+    pub fn reset(&mut self, f: impl FnOnce(&mut T) -> Result<(), String>) -> Result<(), String> {
+        match self {
+            Self::Owned(backend) => f(backend),
+            Self::Merged { serial, shared } => {
+                let mut merged = shared.lock().unwrap();
+                if merged.owner.as_ref() != Some(serial) {
+                    return Ok(());
+                }
+                merged.owner = None;
+                f(&mut merged.backend)
+            }
+        }
+    }
+}
+
+// Shared backends of each merge group, alive while any device links them
+#[derive(Default)]
+pub struct MergeRegistry {
+    stylus: HashMap<String, Weak<Mutex<Merged<StylusBackend>>>>,
+    finger: HashMap<String, Weak<Mutex<Merged<FingerBackend>>>>,
+}
+
+fn get_or_create<T>(
+    map: &mut HashMap<String, Weak<Mutex<Merged<T>>>>,
+    group: &str,
+    create: impl FnOnce() -> Result<T, String>,
+) -> Result<SharedBackend<T>, String> {
+    if let Some(shared) = map.get(group).and_then(Weak::upgrade) {
+        return Ok(shared);
+    }
+    let shared = Arc::new(Mutex::new(Merged {
+        backend: create()?,
+        owner: None,
+    }));
+    map.insert(group.to_string(), Arc::downgrade(&shared));
+    Ok(shared)
+}
+
+impl MergeRegistry {
+    pub fn stylus(
+        &mut self,
+        group: &str,
+        create: impl FnOnce() -> Result<StylusBackend, String>,
+    ) -> Result<SharedBackend<StylusBackend>, String> {
+        get_or_create(&mut self.stylus, group, create)
+    }
+
+    pub fn finger(
+        &mut self,
+        group: &str,
+        create: impl FnOnce() -> Result<FingerBackend, String>,
+    ) -> Result<SharedBackend<FingerBackend>, String> {
+        get_or_create(&mut self.finger, group, create)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use evdev::{EventType, KeyCode};
+
+    use super::*;
+    use crate::{backend::evdev::sink::CaptureSink, parse::Stylus, profile::BarrelConfig};
+
+    // Key events of every emitted frame
+    type KeyFrames = Vec<Vec<(KeyCode, i32)>>;
+
+    const PEN: KeyCode = KeyCode::BTN_TOOL_PEN;
+    const TOUCH: KeyCode = KeyCode::BTN_TOUCH;
+
+    enum Step {
+        // Tablet index, hover, down
+        Pen(usize, bool, bool),
+        Reset(usize),
+    }
+    use Step::*;
+
+    fn pen(hover: bool, down: bool) -> Stylus {
+        Stylus {
+            down,
+            button: false,
+            hover,
+            button2: false,
+            eraser: false,
+            pressure: if down { 100 } else { 0 },
+            tilt_x: 0,
+            tilt_y: 0,
+            x: 10,
+            y: 10,
+            timestamp: 0,
+            tool_serial: None,
+        }
+    }
+
+    // Key events of every frame of the shared stylus, driven by two tablets
+    fn run(steps: &[Step]) -> KeyFrames {
+        let sink = CaptureSink::new();
+        let mut registry = MergeRegistry::default();
+        let shared = registry
+            .stylus("group", || {
+                Ok(StylusBackend::from_sink(Box::new(sink.clone())))
+            })
+            .unwrap();
+        let mut links = [
+            BackendLink::merged("first", shared.clone()),
+            BackendLink::merged("second", shared),
+        ];
+
+        let barrel = BarrelConfig::default();
+        for step in steps {
+            match *step {
+                Pen(tablet, hover, down) => links[tablet]
+                    .with(|stylus| stylus.process(&pen(hover, down), &barrel))
+                    .unwrap(),
+                Reset(tablet) => links[tablet].reset(|stylus| stylus.reset()).unwrap(),
+            }
+        }
+        sink.take()
+            .iter()
+            .map(|frame| {
+                frame
+                    .iter()
+                    .filter(|event| event.event_type() == EventType::KEY)
+                    .map(|event| (KeyCode(event.code()), event.value()))
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn owner_arbitration() {
+        let cases: &[(&str, Vec<Step>, KeyFrames)] = &[
+            (
+                "first in proximity owns the device",
+                vec![Pen(0, true, false), Pen(1, true, false)],
+                vec![vec![(PEN, 1)]],
+            ),
+            (
+                "others are dropped while the owner draws",
+                vec![
+                    Pen(0, true, false),
+                    Pen(0, true, true),
+                    Pen(1, true, true),
+                    Pen(1, true, false),
+                    Pen(0, true, false),
+                ],
+                vec![vec![(PEN, 1)], vec![(TOUCH, 1)], vec![(TOUCH, 0)]],
+            ),
+            (
+                "ownership passes after the owner leaves",
+                vec![
+                    Pen(0, true, false),
+                    Pen(0, false, false),
+                    Pen(1, true, false),
+                ],
+                vec![vec![(PEN, 1)], vec![(PEN, 0)], vec![(PEN, 1)]],
+            ),
+            (
+                "reset of another tablet keeps the owner's tool",
+                vec![Pen(0, true, true), Reset(1), Pen(1, true, false)],
+                vec![vec![(PEN, 1), (TOUCH, 1)]],
+            ),
+            (
+                "reset of the owner releases the device",
+                vec![Pen(0, true, true), Reset(0), Pen(1, true, false)],
+                vec![
+                    vec![(PEN, 1), (TOUCH, 1)],
+                    vec![(TOUCH, 0), (PEN, 0)],
+                    vec![(PEN, 1)],
+                ],
+            ),
+        ];
+
+        for (name, steps, expected) in cases {
+            assert_eq!(&run(steps), expected, "{name}");
+        }
+    }
+}
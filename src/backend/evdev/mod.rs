@@ -1,31 +1,604 @@
 mod event_list;
 mod finger;
+mod keyboard;
+mod merge;
+mod naming;
+mod precision;
+mod sink;
 mod stylus;
 mod with_abs;
 
-use crate::backend::BackendConfig;
+use crate::{
+    backend::{
+        BackendConfig, DeviceIdentity,
+        gesture::{Gesture, GestureRecognizer},
+        keymap, tilt,
+    },
+    connect_ws::execute_command,
+    outbound::{Outbound, OutboundSender},
+    profile::{Action, Config, Mode, Profile, Region, TouchKind},
+};
 
-use super::super::parse::{Event, Init};
-use finger::FingerBackend;
-use stylus::StylusBackend;
+use super::super::parse::{Button, Event, Finger, Init, Key};
+use evdev::KeyCode;
+use keyboard::KeyboardBackend;
+use merge::BackendLink;
+use precision::Precision;
+use std::collections::HashMap;
+use tokio::time::Instant;
+
+pub use finger::FingerBackend;
+pub use merge::MergeRegistry;
+pub use sink::{CaptureSink, EventSink};
+pub use stylus::StylusBackend;
 
 pub struct InputBackend {
-    stylus: StylusBackend,
-    finger: FingerBackend,
+    config: BackendConfig,
+    identity: DeviceIdentity,
+    init_data: Init,
+    // None while the mode does not use the device
+    stylus: Option<BackendLink<StylusBackend>>,
+    finger: Option<BackendLink<FingerBackend>>,
+    mode: Mode,
+    // Where events are placed on the surface, from the profile or the preset
+    region: Region,
+    preset: Option<usize>,
+    precision: Precision,
+    surface: (u16, u16),
+    profile: Profile,
+    // Created on the first key / click action
+    keyboard: Option<KeyboardBackend>,
+    // Pad buttons held now and the action they pressed
+    pad_pressed: HashMap<u8, Action>,
+    gestures: Option<GestureRecognizer>,
+    // Timestamp of the last finger event and when it arrived, gestures follow the tablet clock
+    gesture_clock: Option<(i32, Instant)>,
+    // Android keycodes held now and the keys they pressed
+    keys_pressed: HashMap<u16, Vec<KeyCode>>,
+    // Channel to the connected tablet
+    outbound: Option<OutboundSender>,
+    pressure_clicked: bool,
 }
 impl InputBackend {
-    pub fn new(config: BackendConfig, init_data: &Init) -> Result<Self, String> {
-        Ok(Self {
-            stylus: StylusBackend::new(&config, init_data)?,
-            finger: FingerBackend::new(&config, init_data)?,
-        })
+    pub fn new(
+        config: BackendConfig,
+        identity: &DeviceIdentity,
+        profile: &Profile,
+        profile_config: &Config,
+        registry: &mut MergeRegistry,
+        init_data: &Init,
+    ) -> Result<Self, String> {
+        let mut backend = Self {
+            init_data: init_data.clone(),
+            stylus: None,
+            finger: None,
+            mode: profile.mode,
+            region: profile.region,
+            preset: None,
+            precision: Precision::default(),
+            surface: (init_data.width, init_data.height),
+            profile: profile.clone(),
+            keyboard: None,
+            pad_pressed: HashMap::new(),
+            gestures: GestureRecognizer::from_profile(profile, init_data),
+            gesture_clock: None,
+            keys_pressed: HashMap::new(),
+            outbound: None,
+            pressure_clicked: false,
+            config,
+            identity: identity.clone(),
+        };
+
+        // Shared devices are built for the surface of the group and kept on mode switches
+        if let Some(ref group) = profile.merge_group {
+            let merge_group = profile_config.group(group);
+            let surface = Init {
+                width: merge_group.width,
+                height: merge_group.height,
+                tilt_format: init_data.tilt_format,
+                pressure_max: init_data.pressure_max,
+                // The physical size of the shared surface is unknown
+                width_mm: 0,
+                height_mm: 0,
+                tilt_max: init_data.tilt_max,
+            };
+            let group_identity = DeviceIdentity::new(group, None);
+            let config = &backend.config;
+
+            if profile.merge_stylus {
+                backend.stylus = Some(BackendLink::merged(
+                    &identity.serial,
+                    registry.stylus(group, || {
+                        StylusBackend::new(config, &group_identity, &surface)
+                    })?,
+                ));
+            }
+            if profile.merge_touchpad {
+                let kind = profile.mode.touch().unwrap_or(TouchKind::Touchpad);
+                backend.finger = Some(BackendLink::merged(
+                    &identity.serial,
+                    registry.finger(group, || {
+                        FingerBackend::new(config, &group_identity, &surface, kind)
+                    })?,
+                ));
+            }
+            backend.surface = (surface.width, surface.height);
+        }
+
+        backend.apply_mode()?;
+        Ok(backend)
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: Mode) -> Result<(), String> {
+        if mode == self.mode {
+            return Ok(());
+        }
+        tracing::info!("{} switched to {} mode", self.identity.serial, mode);
+        self.mode = mode;
+        if self.profile.haptic.on_mode_switch {
+            self.haptic();
+        }
+        self.apply_mode()
+    }
+
+    // Name of the selected preset
+    pub fn preset(&self) -> Option<&str> {
+        self.preset
+            .map(|index| self.profile.presets[index].name.as_str())
+    }
+
+    pub fn set_preset(&mut self, name: &str) -> Result<(), String> {
+        let index = self
+            .profile
+            .preset_index(name)
+            .ok_or_else(|| format!("Unknown preset {}", name))?;
+        self.select_preset(index);
+        Ok(())
+    }
+
+    pub fn cycle_preset(&mut self) {
+        if !self.profile.presets.is_empty() {
+            let next = self.preset.map_or(0, |index| index + 1);
+            self.select_preset(next % self.profile.presets.len());
+        }
+    }
+
+    fn select_preset(&mut self, index: usize) {
+        let preset = &self.profile.presets[index];
+        tracing::info!(
+            "{} switched to {} preset",
+            self.identity.serial,
+            preset.name
+        );
+        self.region = preset.region;
+        self.preset = Some(index);
+        if self.profile.notify_preset
+            && let Some(ref outbound) = self.outbound
+        {
+            let _ = outbound.send(Outbound::Preset {
+                name: preset.name.clone(),
+            });
+        }
+    }
+
+    pub fn precision(&self) -> bool {
+        self.precision.is_on()
+    }
+
+    fn log_precision(&self, precision: bool) {
+        tracing::info!(
+            "{} precision mode {}",
+            self.identity.serial,
+            if precision { "on" } else { "off" }
+        );
+    }
+
+    pub fn set_precision(&mut self, precision: bool) {
+        if precision != self.precision.is_on() {
+            self.log_precision(precision);
+        }
+        self.precision.set(precision);
+    }
+
+    // Lifting the pen or leaving proximity ends the mode
+    fn apply_precision(&mut self, x: i16, y: i16, down: bool, in_proximity: bool) -> (i16, i16) {
+        let was_on = self.precision.is_on();
+        let point = self
+            .precision
+            .apply(x, y, down, in_proximity, self.profile.precision_scale);
+        if was_on && !self.precision.is_on() {
+            self.log_precision(false);
+        }
+        point
+    }
+
+    pub fn cycle_mode(&mut self) -> Result<(), String> {
+        self.set_mode(self.profile.next_mode(self.mode))
+    }
+
+    // Create the devices used by the mode, destroy the owned ones it does not use.
+    // Shared devices stay alive for the other tablets and are only released.
+    fn apply_mode(&mut self) -> Result<(), String> {
+        if !self.mode.has_stylus() {
+            self.reset_stylus()?;
+            if let Some(BackendLink::Owned(_)) = self.stylus {
+                self.stylus = None;
+            }
+        } else if self.stylus.is_none() {
+            self.stylus = Some(BackendLink::Owned(StylusBackend::new(
+                &self.config,
+                &self.identity,
+                &self.init_data,
+            )?));
+        }
+
+        let touch = self.mode.touch();
+        let stale = match self.finger {
+            Some(BackendLink::Owned(ref finger)) => Some(finger.kind()) != touch,
+            Some(_) => touch.is_none(),
+            None => false,
+        };
+        if stale {
+            self.reset_finger()?;
+            if let Some(BackendLink::Owned(_)) = self.finger {
+                self.finger = None;
+            }
+        }
+        if let Some(kind) = touch
+            && self.finger.is_none()
+        {
+            self.finger = Some(BackendLink::Owned(FingerBackend::new(
+                &self.config,
+                &self.identity,
+                &self.init_data,
+                kind,
+            )?));
+        }
+        Ok(())
+    }
+
+    pub fn set_outbound(&mut self, outbound: Option<OutboundSender>) {
+        self.outbound = outbound;
+    }
+
+    // Ask the tablet to vibrate
+    fn haptic(&self) {
+        if let Some(ref outbound) = self.outbound {
+            let _ = outbound.send(Outbound::Haptic {
+                duration: self.profile.haptic.duration,
+                amplitude: self.profile.haptic.amplitude,
+            });
+        }
+    }
+
+    // Whether the backend was built for the same geometry
+    pub fn matches(&self, init_data: &Init) -> bool {
+        self.init_data == *init_data
+    }
+
+    // Whether points go through the region mapping
+    fn is_mapped(&self, merged: bool) -> bool {
+        merged || self.region != Region::default()
+    }
+
+    // Place a point of this tablet into its region of the surface
+    fn map_point(&self, x: i16, y: i16) -> (i16, i16) {
+        let (x, y) = self.region.map(
+            x as i32,
+            y as i32,
+            (self.init_data.width, self.init_data.height),
+            self.surface,
+        );
+        (x as i16, y as i16)
     }
 
     pub fn execute(&mut self, event: Event) -> Result<(), String> {
         match event {
-            Event::Finger(finger_data) => self.finger.process(&finger_data),
-            Event::Stylus(stylus_data) => self.stylus.process(&stylus_data),
+            Event::Finger(finger_data) => self.process_fingers(vec![finger_data], None),
+            Event::FingerFrame(frame) => self.process_fingers(frame.fingers, Some(frame.timestamp)),
+            Event::Stylus(mut stylus_data) => {
+                if !self.mode.has_stylus() {
+                    return Ok(());
+                }
+                let Some(ref stylus) = self.stylus else {
+                    return Ok(());
+                };
+                let mapped = self.is_mapped(stylus.is_merged());
+
+                if let Some(threshold) = self.profile.haptic.pressure_click {
+                    let clicked = stylus_data.down && stylus_data.pressure >= threshold;
+                    if clicked && !self.pressure_clicked {
+                        self.haptic();
+                    }
+                    self.pressure_clicked = clicked;
+                }
+
+                if mapped {
+                    (stylus_data.x, stylus_data.y) = self.map_point(stylus_data.x, stylus_data.y);
+                }
+                (stylus_data.tilt_x, stylus_data.tilt_y) = tilt::convert(
+                    &self.profile.tilt,
+                    self.init_data.tilt_format,
+                    self.init_data.tilt_max,
+                    stylus_data.tilt_x,
+                    stylus_data.tilt_y,
+                );
+                (stylus_data.x, stylus_data.y) = self.apply_precision(
+                    stylus_data.x,
+                    stylus_data.y,
+                    stylus_data.down,
+                    stylus_data.hover || stylus_data.down,
+                );
+                let barrel = &self.profile.barrel;
+                let mut actions = Vec::new();
+                let result = match self.stylus {
+                    Some(ref mut stylus) => stylus.with(|stylus| {
+                        let result = stylus.process(&stylus_data, barrel);
+                        actions = stylus.take_actions();
+                        result
+                    }),
+                    None => Ok(()),
+                };
+                self.run_actions(actions).and(result)
+            }
+            Event::Button(button_data) => self.process_button(&button_data),
+            Event::Key(key_data) => self.process_key(key_data),
             Event::Init(_) => Ok(()),
         }
     }
+
+    fn process_fingers(
+        &mut self,
+        mut fingers: Vec<Finger>,
+        timestamp: Option<i32>,
+    ) -> Result<(), String> {
+        if self.gestures.is_some() {
+            // Events without a timestamp continue the clock of the previous one
+            let timestamp = timestamp.unwrap_or_else(|| self.gesture_time());
+            self.gesture_clock = Some((timestamp, Instant::now()));
+            let recognizer = self.gestures.as_mut().unwrap();
+            let gestures = fingers
+                .iter()
+                .flat_map(|finger_data| recognizer.process(finger_data, timestamp))
+                .collect::<Vec<_>>();
+            for gesture in gestures {
+                self.process_gesture(gesture)?;
+            }
+            if !self.profile.gestures.passthrough {
+                return Ok(());
+            }
+        }
+
+        // Shared device of a mode without touch input is quiesced
+        if self.mode.touch().is_none() {
+            return Ok(());
+        }
+        let Some(ref finger) = self.finger else {
+            return Ok(());
+        };
+        // Touchpad moves relatively, only a touchscreen follows the region alone
+        let mapped = finger.is_merged()
+            || (self.mode.touch() == Some(TouchKind::Touchscreen) && self.is_mapped(false));
+        if mapped {
+            for finger_data in fingers.iter_mut() {
+                if finger_data.x != -1 && finger_data.y != -1 {
+                    (finger_data.x, finger_data.y) = self.map_point(finger_data.x, finger_data.y);
+                }
+            }
+        }
+        match self.finger {
+            Some(ref mut finger) => finger.with(|finger| finger.process(&fingers, timestamp)),
+            None => Ok(()),
+        }
+    }
+
+    // Run the action mapped to the pad button
+    fn process_button(&mut self, button_data: &Button) -> Result<(), String> {
+        let action = if button_data.pressed {
+            if self.pad_pressed.contains_key(&button_data.id) {
+                return Ok(());
+            }
+            let action = self.profile.button(button_data.id).clone();
+            self.pad_pressed.insert(button_data.id, action.clone());
+            action
+        } else {
+            match self.pad_pressed.remove(&button_data.id) {
+                Some(action) => action,
+                None => return Ok(()),
+            }
+        };
+
+        match action {
+            Action::EraserToggle if button_data.pressed => self.trigger(action),
+            action => self.run_actions(vec![(action, button_data.pressed)]),
+        }
+    }
+
+    fn process_key(&mut self, key_data: Key) -> Result<(), String> {
+        match key_data {
+            Key::Code { code, pressed } => {
+                if pressed {
+                    // Key repeat of Android sends down again
+                    if self.keys_pressed.contains_key(&code) {
+                        return Ok(());
+                    }
+                    let keys = match self.profile.keyboard.keymap.get(&code.to_string()) {
+                        Some(chord) => chord.0.clone(),
+                        None => match keymap::android_keycode(code) {
+                            Some(key) => vec![key],
+                            None => {
+                                tracing::debug!("No key mapped for keycode {}", code);
+                                return Ok(());
+                            }
+                        },
+                    };
+                    self.keyboard()?.chord(&keys, true)?;
+                    self.keys_pressed.insert(code, keys);
+                } else if let Some(keys) = self.keys_pressed.remove(&code) {
+                    self.keyboard()?.chord(&keys, false)?;
+                }
+                Ok(())
+            }
+            Key::Text(text) => {
+                for character in text.chars() {
+                    self.type_character(character)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    // Type with the layout, or enter the code point when no key makes the character
+    fn type_character(&mut self, character: char) -> Result<(), String> {
+        let keys = match self.profile.keyboard.layout.get(&character.to_string()) {
+            Some(chord) => Some(chord.0.clone()),
+            None => keymap::us_layout(character),
+        };
+        if let Some(keys) = keys {
+            return self.keyboard()?.tap(&keys);
+        }
+
+        let unicode_input = self.profile.keyboard.unicode_input.0.clone();
+        if unicode_input.is_empty() {
+            tracing::warn!("No key mapped for character {:?}", character);
+            return Ok(());
+        }
+        let keyboard = self.keyboard()?;
+        keyboard.tap(&unicode_input)?;
+        for digit in format!("{:x}", character as u32).chars() {
+            if let Some(key) = keymap::hex_digit(digit) {
+                keyboard.tap(&[key])?;
+            }
+        }
+        keyboard.tap(&[KeyCode::KEY_SPACE])
+    }
+
+    // Tablet time now, estimated from the last finger event
+    fn gesture_time(&self) -> i32 {
+        match self.gesture_clock {
+            Some((timestamp, at)) => timestamp.wrapping_add(at.elapsed().as_millis() as i32),
+            None => 0,
+        }
+    }
+
+    // Fire gestures which need no new event, such as a long press held still
+    pub fn tick(&mut self) -> Result<(), String> {
+        if self.gesture_clock.is_none() {
+            return Ok(());
+        }
+        let timestamp = self.gesture_time();
+        let Some(ref mut recognizer) = self.gestures else {
+            return Ok(());
+        };
+        match recognizer.tick(timestamp) {
+            Some(gesture) => self.process_gesture(gesture),
+            None => Ok(()),
+        }
+    }
+
+    fn process_gesture(&mut self, gesture: Gesture) -> Result<(), String> {
+        let name = gesture.to_string();
+        tracing::debug!("Gesture recognized: {}", name);
+        match self.profile.gestures.actions.get(&name) {
+            Some(action) => {
+                if self.profile.haptic.on_gesture {
+                    self.haptic();
+                }
+                self.trigger(action.clone())
+            }
+            None => Ok(()),
+        }
+    }
+
+    // Press and release the action at once
+    pub fn trigger(&mut self, action: Action) -> Result<(), String> {
+        match action {
+            Action::EraserToggle => match self.stylus {
+                Some(ref mut stylus) => stylus.with(|stylus| {
+                    stylus.toggle_eraser();
+                    Ok(())
+                }),
+                None => Ok(()),
+            },
+            action => self.run_actions(vec![(action.clone(), true), (action, false)]),
+        }
+    }
+
+    fn keyboard(&mut self) -> Result<&mut KeyboardBackend, String> {
+        if self.keyboard.is_none() {
+            self.keyboard = Some(KeyboardBackend::new(&self.config, &self.identity)?);
+        }
+        Ok(self.keyboard.as_mut().unwrap())
+    }
+
+    // Press / release actions which are not part of the stylus device
+    fn run_actions(&mut self, actions: Vec<(Action, bool)>) -> Result<(), String> {
+        for (action, pressed) in actions {
+            match action {
+                Action::RightClick => self.keyboard()?.chord(&[KeyCode::BTN_RIGHT], pressed)?,
+                Action::Keys(keys) => self.keyboard()?.chord(&keys, pressed)?,
+                Action::Scroll { keys, x, y } if pressed => self.keyboard()?.scroll(&keys, x, y)?,
+                Action::Command(command) if pressed => {
+                    execute_command(&command, &self.identity.serial)
+                }
+                Action::Mode(mode) if pressed => self.set_mode(mode)?,
+                Action::CycleMode if pressed => self.cycle_mode()?,
+                Action::Preset(name) if pressed => self.set_preset(&name)?,
+                Action::CyclePreset if pressed => self.cycle_preset(),
+                Action::Precision if pressed => self.set_precision(!self.precision()),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn reset_stylus(&mut self) -> Result<(), String> {
+        let mut actions = Vec::new();
+        let result = match self.stylus {
+            Some(ref mut stylus) => stylus.reset(|stylus| {
+                let result = stylus.reset();
+                actions = stylus.take_actions();
+                result
+            }),
+            None => Ok(()),
+        };
+        self.run_actions(actions).and(result)
+    }
+
+    fn reset_finger(&mut self) -> Result<(), String> {
+        match self.finger {
+            Some(ref mut finger) => finger.reset(|finger| finger.reset()),
+            None => Ok(()),
+        }
+    }
+
+    // Release all contacts and tools, used before the backend is dropped or re-initialized
+    pub fn reset(&mut self) -> Result<(), String> {
+        self.set_precision(false);
+        let stylus = self.reset_stylus();
+        let finger = self.reset_finger();
+        if let Some(ref mut recognizer) = self.gestures {
+            recognizer.reset();
+        }
+        self.gesture_clock = None;
+        let actions = self
+            .pad_pressed
+            .drain()
+            .map(|(_, action)| (action, false))
+            .collect();
+        let mut keyboard = self.run_actions(actions);
+        // Soft keyboard keys held when the tablet went away
+        let keys_pressed = self.keys_pressed.drain().collect::<Vec<_>>();
+        for (_, keys) in keys_pressed {
+            keyboard = keyboard.and(
+                self.keyboard()
+                    .and_then(|device| device.chord(&keys, false)),
+            );
+        }
+        stylus.and(finger).and(keyboard)
+    }
 }
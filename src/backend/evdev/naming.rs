@@ -0,0 +1,29 @@
+use std::ffi::CString;
+
+use qwreey_utility_rs::ErrToString;
+
+use crate::backend::DeviceIdentity;
+
+// uinput 장치 이름 / phys 를 기기 serial 또는 profile 이름으로부터 만듭니다
+
+// UINPUT_MAX_NAME_SIZE (80) including the null terminator
+const MAX_NAME_LEN: usize = 79;
+
+// e.g. "pendroid-stylus R52N80ABCDE"
+pub fn device_name(kind: &str, identity: &DeviceIdentity) -> String {
+    let mut name = format!("pendroid-{} {}", kind, identity.label);
+    if name.len() > MAX_NAME_LEN {
+        let mut end = MAX_NAME_LEN;
+        while !name.is_char_boundary(end) {
+            end -= 1;
+        }
+        name.truncate(end);
+    }
+    name
+}
+
+// uinput can not set the uniq string, so the serial is carried in phys
+// e.g. "pendroid/R52N80ABCDE/stylus"
+pub fn device_phys(kind: &str, identity: &DeviceIdentity) -> Result<CString, String> {
+    CString::new(format!("pendroid/{}/{}", identity.serial, kind)).err_to_string()
+}
@@ -0,0 +1,103 @@
+// 정밀 모드 상태.
+// 켜진 뒤 첫 stylus 위치를 기준점으로 삼고, 이후 움직임을 기준점 주변으로 축소합니다.
+// 펜을 떼거나 펜이 proximity 를 벗어나면 꺼집니다.
+
+#[derive(Default)]
+pub struct Precision {
+    on: bool,
+    // Pen position when the mode started, set by the next stylus event
+    anchor: Option<(i16, i16)>,
+    // Pen was down on the last stylus event
+    down: bool,
+}
+
+impl Precision {
+    pub fn is_on(&self) -> bool {
+        self.on
+    }
+
+    pub fn set(&mut self, on: bool) {
+        self.on = on;
+        self.anchor = None;
+    }
+
+    // Scale the movement around the anchor. The lift is still scaled so the pen
+    // does not jump while releasing, later events are not.
+    pub fn apply(
+        &mut self,
+        x: i16,
+        y: i16,
+        down: bool,
+        in_proximity: bool,
+        scale: f32,
+    ) -> (i16, i16) {
+        let lifted = self.down && !down;
+        self.down = down;
+        if !self.on {
+            return (x, y);
+        }
+        if !in_proximity {
+            self.set(false);
+            return (x, y);
+        }
+
+        let anchor = *self.anchor.get_or_insert((x, y));
+        let scale_axis =
+            |value: i16, anchor: i16| anchor + ((value - anchor) as f32 * scale).round() as i16;
+        let point = (scale_axis(x, anchor.0), scale_axis(y, anchor.1));
+        if lifted {
+            self.set(false);
+        }
+        point
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCALE: f32 = 0.25;
+
+    #[test]
+    fn scales_around_anchor() {
+        let mut precision = Precision::default();
+        precision.set(true);
+        assert_eq!(precision.apply(100, 100, true, true, SCALE), (100, 100));
+        assert_eq!(precision.apply(140, 60, true, true, SCALE), (110, 90));
+        assert!(precision.is_on());
+    }
+
+    #[test]
+    fn lift_ends_mode() {
+        let mut precision = Precision::default();
+        precision.set(true);
+        precision.apply(100, 100, true, true, SCALE);
+
+        // The lift itself stays at the scaled position
+        assert_eq!(precision.apply(140, 100, false, true, SCALE), (110, 100));
+        assert!(!precision.is_on());
+        // Hovering after the lift moves 1:1
+        assert_eq!(precision.apply(180, 100, false, true, SCALE), (180, 100));
+    }
+
+    #[test]
+    fn hover_keeps_mode_until_lift() {
+        let mut precision = Precision::default();
+        precision.set(true);
+        // Turned on while hovering, the anchor is the hover position
+        assert_eq!(precision.apply(100, 100, false, true, SCALE), (100, 100));
+        assert_eq!(precision.apply(140, 100, true, true, SCALE), (110, 100));
+        assert!(precision.is_on());
+        precision.apply(140, 100, false, true, SCALE);
+        assert!(!precision.is_on());
+    }
+
+    #[test]
+    fn leaving_proximity_ends_mode() {
+        let mut precision = Precision::default();
+        precision.set(true);
+        precision.apply(100, 100, false, true, SCALE);
+        assert_eq!(precision.apply(140, 100, false, false, SCALE), (140, 100));
+        assert!(!precision.is_on());
+    }
+}
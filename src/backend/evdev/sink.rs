@@ -0,0 +1,40 @@
+use std::sync::{Arc, Mutex};
+
+use evdev::{InputEvent, uinput::VirtualDevice};
+
+// Backend 가 만든 evdev frame 을 받는 대상.
+// 실제로는 uinput 장치이고, 테스트와 fuzz 에서는 다른 sink 로 대체할 수 있습니다.
+pub trait EventSink: Send + Sync {
+    // Write the events followed by SYN_REPORT
+    fn emit(&mut self, events: &[InputEvent]) -> std::io::Result<()>;
+}
+
+impl EventSink for VirtualDevice {
+    fn emit(&mut self, events: &[InputEvent]) -> std::io::Result<()> {
+        VirtualDevice::emit(self, events)
+    }
+}
+
+// Keeps every emitted frame instead of writing it, shared between clones
+#[derive(Clone, Default)]
+pub struct CaptureSink {
+    frames: Arc<Mutex<Vec<Vec<InputEvent>>>>,
+}
+
+impl CaptureSink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Frames emitted since the last call
+    pub fn take(&self) -> Vec<Vec<InputEvent>> {
+        std::mem::take(&mut self.frames.lock().unwrap())
+    }
+}
+
+impl EventSink for CaptureSink {
+    fn emit(&mut self, events: &[InputEvent]) -> std::io::Result<()> {
+        self.frames.lock().unwrap().push(events.to_vec());
+        Ok(())
+    }
+}
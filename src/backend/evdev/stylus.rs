@@ -1,8 +1,14 @@
-use crate::backend::BackendConfig;
+use crate::{
+    backend::{BackendConfig, DeviceIdentity},
+    profile::{Action, BarrelConfig},
+};
 
 use super::{
     super::super::parse::{Init, Stylus},
     event_list::{EventList, GetInputs, PushEvent},
+    merge::Arbitrated,
+    naming::{device_name, device_phys},
+    sink::EventSink,
     with_abs::WithAbs,
 };
 use evdev::{
@@ -10,7 +16,6 @@ use evdev::{
     PropType, UinputAbsSetup, uinput::VirtualDevice,
 };
 use qwreey_utility_rs::ErrToString;
-use std::sync::LazyLock;
 
 const ABS_X: u16 = AbsoluteAxisCode::ABS_X.0;
 const ABS_Y: u16 = AbsoluteAxisCode::ABS_Y.0;
@@ -18,72 +23,87 @@ const ABS_PRESSURE: u16 = AbsoluteAxisCode::ABS_PRESSURE.0;
 const ABS_TILT_X: u16 = AbsoluteAxisCode::ABS_TILT_X.0;
 const ABS_TILT_Y: u16 = AbsoluteAxisCode::ABS_TILT_Y.0;
 
-const BARREL_TIMEOUT: i32 = 800;
-
 pub struct StylusBackend {
-    device: VirtualDevice,
+    device: Box<dyn EventSink>,
     current_down: bool,
     current_hover: bool,
-    current_button: bool,
+    // Tool in proximity is the eraser
+    current_rubber: bool,
     inputs: EventList,
     barrel_timestamp: i32,
+    long_press_timestamp: i32,
     last_button: bool,
-    barrel_activated: bool,
+    // Barrel action held now, released on button release
+    barrel_action: Action,
+    tap_release: bool,
+    eraser_hold: bool,
+    eraser_toggled: bool,
+    current_button2: bool,
+    // Actions emitted by the keyboard backend
+    actions: Vec<(Action, bool)>,
 }
 
-static RUBBER_OFF: LazyLock<EventList> = LazyLock::new(|| {
-    let mut events = EventList::with_capacity(1);
-    events.push_key(&KeyCode::BTN_TOOL_RUBBER, 0);
-    events
-});
-
-static PEN_OFF: LazyLock<EventList> = LazyLock::new(|| {
-    let mut events = EventList::with_capacity(1);
-    events.push_key(&KeyCode::BTN_TOOL_PEN, 0);
-    events
-});
-
 impl GetInputs for StylusBackend {
     fn get_inputs(&mut self) -> &mut EventList {
         &mut self.inputs
     }
 }
 
+impl Arbitrated for StylusBackend {
+    fn in_proximity(&self) -> bool {
+        self.current_hover || self.current_down
+    }
+}
+
 impl StylusBackend {
     // Create new evdev device
-    pub fn new(_config: &BackendConfig, init_data: &Init) -> Result<Self, String> {
+    pub fn new(
+        config: &BackendConfig,
+        identity: &DeviceIdentity,
+        init_data: &Init,
+    ) -> Result<Self, String> {
+        let name = device_name("stylus", identity);
+        let (res_x, res_y) = init_data.resolution();
+        let tilt_max = init_data.tilt_max as i32;
         let mut device = VirtualDevice::builder()
             .err_to_string()?
-            .name("pendroid-stylus")
-            .input_id(InputId::new(BusType::BUS_USB, 0u16, 1332u16, 1u16))
+            .name(&name)
+            .input_id(InputId::new(
+                BusType::BUS_USB,
+                config.evdev_vendor_id,
+                config.evdev_stylus_product_id,
+                1u16,
+            ))
+            .with_phys(&device_phys("stylus", identity)?)
+            .err_to_string()?
             .with_abs(&[
                 // ABS PRESSURE
                 UinputAbsSetup::new(
                     AbsoluteAxisCode::ABS_PRESSURE,
-                    AbsInfo::new(0, 0, 4096, 0, 0, 1),
+                    AbsInfo::new(0, 0, init_data.pressure_max as i32, 0, 0, 1),
                 ),
                 // TOOL INFO
                 UinputAbsSetup::new(
                     AbsoluteAxisCode::ABS_MT_TOOL_TYPE,
                     AbsInfo::new(1, 0, 0, 0, 0, 1),
                 ),
-                // ABS TILT X / Y
+                // ABS TILT X / Y, centidegrees (resolution is units per radian)
                 UinputAbsSetup::new(
                     AbsoluteAxisCode::ABS_TILT_X,
-                    AbsInfo::new(0, -9000, 9000, 0, 0, 5730),
+                    AbsInfo::new(0, -tilt_max, tilt_max, 0, 0, 5730),
                 ),
                 UinputAbsSetup::new(
                     AbsoluteAxisCode::ABS_TILT_Y,
-                    AbsInfo::new(0, -9000, 9000, 0, 0, 5730),
+                    AbsInfo::new(0, -tilt_max, tilt_max, 0, 0, 5730),
                 ),
                 // ABS X / Y
                 UinputAbsSetup::new(
                     AbsoluteAxisCode::ABS_X,
-                    AbsInfo::new(0, 0, init_data.width as i32, 0, 0, 1),
+                    AbsInfo::new(0, 0, init_data.width as i32, 0, 0, res_x),
                 ),
                 UinputAbsSetup::new(
                     AbsoluteAxisCode::ABS_Y,
-                    AbsInfo::new(0, 0, init_data.height as i32, 0, 0, 1),
+                    AbsInfo::new(0, 0, init_data.height as i32, 0, 0, res_y),
                 ),
             ])?
             .with_keys(&AttributeSet::from_iter([
@@ -91,9 +111,13 @@ impl StylusBackend {
                 KeyCode::BTN_TOOL_RUBBER,
                 KeyCode::BTN_TOUCH,
                 KeyCode::BTN_STYLUS,
+                KeyCode::BTN_STYLUS2,
             ]))
             .err_to_string()?
-            .with_msc(&AttributeSet::from_iter([MiscCode::MSC_TIMESTAMP]))
+            .with_msc(&AttributeSet::from_iter([
+                MiscCode::MSC_TIMESTAMP,
+                MiscCode::MSC_SERIAL,
+            ]))
             .err_to_string()?
             .with_properties(&AttributeSet::from_iter([PropType::POINTER]))
             .err_to_string()?
@@ -105,21 +129,116 @@ impl StylusBackend {
             tracing::info!("New stylus backend available as {}", path.display());
         }
 
-        Ok(Self {
+        Ok(Self::from_sink(Box::new(device)))
+    }
+
+    // Backend writing to any sink instead of a uinput device
+    pub fn from_sink(device: Box<dyn EventSink>) -> Self {
+        Self {
             device,
             inputs: Vec::<InputEvent>::with_capacity(32),
             current_down: false,
             current_hover: false,
-            current_button: false,
+            current_rubber: false,
             barrel_timestamp: -1,
+            long_press_timestamp: -1,
             last_button: false,
-            barrel_activated: false,
-        })
+            barrel_action: Action::Off,
+            tap_release: false,
+            eraser_hold: false,
+            eraser_toggled: false,
+            current_button2: false,
+            actions: Vec::new(),
+        }
     }
 
-    pub fn process(&mut self, pen_data: &Stylus) -> Result<(), String> {
+    // Actions which should be run by the keyboard backend
+    pub fn take_actions(&mut self) -> Vec<(Action, bool)> {
+        std::mem::take(&mut self.actions)
+    }
+
+    // Flip the eraser mode, applied on the next hover
+    pub fn toggle_eraser(&mut self) {
+        self.eraser_toggled = !self.eraser_toggled;
+    }
+
+    fn activate(&mut self, action: &Action) {
+        self.deactivate();
+        match action {
+            Action::Off => {}
+            Action::Eraser => self.eraser_hold = true,
+            Action::EraserToggle => self.toggle_eraser(),
+            Action::Stylus => self.push_key(&KeyCode::BTN_STYLUS, 1),
+            Action::Stylus2 if !self.current_button2 => self.push_key(&KeyCode::BTN_STYLUS2, 1),
+            Action::Stylus2 => {}
+            // Run by the input backend
+            _ => self.actions.push((action.clone(), true)),
+        }
+        self.barrel_action = action.clone();
+    }
+
+    fn deactivate(&mut self) {
+        match std::mem::replace(&mut self.barrel_action, Action::Off) {
+            Action::Eraser => self.eraser_hold = false,
+            Action::Stylus => self.push_key(&KeyCode::BTN_STYLUS, 0),
+            Action::Stylus2 if !self.current_button2 => self.push_key(&KeyCode::BTN_STYLUS2, 0),
+            Action::Off | Action::EraserToggle | Action::Stylus2 => {}
+            action => self.actions.push((action, false)),
+        }
+    }
+
+    // Single / double / long press of the barrel button
+    fn process_barrel(&mut self, pen_data: &Stylus, barrel: &BarrelConfig) {
+        // Release the action tapped on the last frame
+        if self.tap_release {
+            self.tap_release = false;
+            self.deactivate();
+        }
+
+        let button = pen_data.hover && pen_data.button;
+        if button && !self.last_button {
+            if self.barrel_timestamp != -1
+                && barrel.double_press != Action::Off
+                && pen_data.timestamp.wrapping_sub(self.barrel_timestamp)
+                    < barrel.double_press_timeout
+            {
+                // Second clicking
+                self.barrel_timestamp = -1;
+                self.activate(&barrel.double_press);
+            } else {
+                // First clicking, delayed while a long press is possible
+                self.barrel_timestamp = pen_data.timestamp;
+                if barrel.long_press == Action::Off {
+                    self.activate(&barrel.press);
+                } else {
+                    self.long_press_timestamp = pen_data.timestamp;
+                }
+            }
+        } else if !button && self.last_button {
+            if self.long_press_timestamp != -1 {
+                // Released before the long press timeout
+                self.long_press_timestamp = -1;
+                self.activate(&barrel.press);
+                self.tap_release = true;
+            } else {
+                self.deactivate();
+            }
+        }
+
+        if button
+            && self.long_press_timestamp != -1
+            && pen_data.timestamp.wrapping_sub(self.long_press_timestamp)
+                >= barrel.long_press_timeout
+        {
+            self.long_press_timestamp = -1;
+            self.barrel_timestamp = -1;
+            self.activate(&barrel.long_press);
+        }
+        self.last_button = button;
+    }
+
+    pub fn process(&mut self, pen_data: &Stylus, barrel: &BarrelConfig) -> Result<(), String> {
         let hover_changed = pen_data.hover != self.current_hover;
-        let button_changed = pen_data.button != self.current_button;
         self.inputs.clear();
 
         // Report position and pressure
@@ -129,53 +248,51 @@ impl StylusBackend {
         self.push_abs_event(ABS_TILT_X, pen_data.tilt_x as i32);
         self.push_abs_event(ABS_TILT_Y, pen_data.tilt_y as i32);
 
-        // Process double tap action
-        let last_barrel_activated = self.barrel_activated;
-        if !self.last_button && pen_data.button {
-            if self.barrel_timestamp == -1 {
-                // First clicking
-                self.barrel_timestamp = pen_data.timestamp;
-            } else if pen_data.timestamp - self.barrel_timestamp < BARREL_TIMEOUT {
-                // Second clicking
-                self.barrel_activated = true;
-                self.barrel_timestamp = -1;
-            } else {
-                // Timeout
-                self.barrel_timestamp = pen_data.timestamp;
+        self.process_barrel(pen_data, barrel);
+
+        // Second barrel button, shares BTN_STYLUS2 with the stylus2 action
+        let button2 = pen_data.hover && pen_data.button2;
+        if button2 != self.current_button2 {
+            self.current_button2 = button2;
+            if self.barrel_action != Action::Stylus2 {
+                self.push_key(&KeyCode::BTN_STYLUS2, button2 as i32);
             }
         }
-        self.last_button = pen_data.hover && pen_data.button;
-        if self.barrel_activated && (!pen_data.hover || !pen_data.button) {
-            self.barrel_activated = false;
-            self.barrel_timestamp = -1;
-        }
-        if last_barrel_activated != self.barrel_activated {
-            self.push_key(
-                &KeyCode::BTN_STYLUS,
-                if self.barrel_activated { 1 } else { 0 },
-            );
-        }
 
-        // Hover -> Process tool (eraser, pencil)
-        if (hover_changed || button_changed)
-            && pen_data.hover
-            && !pen_data.down
-            && !self.barrel_activated
-        {
-            if pen_data.button {
-                if !hover_changed {
-                    // Disable old tool
-                    self.device.emit(&PEN_OFF).err_to_string()?;
+        // Hover -> Process tool (eraser, pencil), the eraser end always uses the eraser
+        let rubber = pen_data.eraser || self.eraser_hold != self.eraser_toggled;
+        // Entering may come with the pen already down, switching is applied on the lift frame
+        let entering = hover_changed && pen_data.hover;
+        let switching =
+            !hover_changed && pen_data.hover && !pen_data.down && rubber != self.current_rubber;
+        if entering || switching {
+            if switching {
+                // Lift and disable old tool in its own frame, the new tool never arrives touching
+                let mut release = EventList::with_capacity(3);
+                if self.current_down {
+                    release.push_abs_event(ABS_PRESSURE, 0);
+                    release.push_key(&KeyCode::BTN_TOUCH, 0);
+                    self.current_down = false;
                 }
-                self.push_key(&KeyCode::BTN_TOOL_RUBBER, 1);
-            } else {
-                if !hover_changed {
-                    // Disable old tool
-                    self.device.emit(&RUBBER_OFF).err_to_string()?;
-                }
-                self.push_key(&KeyCode::BTN_TOOL_PEN, 1);
+                release.push_key(
+                    if self.current_rubber {
+                        &KeyCode::BTN_TOOL_RUBBER
+                    } else {
+                        &KeyCode::BTN_TOOL_PEN
+                    },
+                    0,
+                );
+                self.device.emit(&release).err_to_string()?;
             }
-            self.current_button = pen_data.button;
+            self.push_key(
+                if rubber {
+                    &KeyCode::BTN_TOOL_RUBBER
+                } else {
+                    &KeyCode::BTN_TOOL_PEN
+                },
+                1,
+            );
+            self.current_rubber = rubber;
         }
 
         // Process pen down (touch)
@@ -187,7 +304,7 @@ impl StylusBackend {
         // Unhover -> Remove all tools
         if hover_changed && !pen_data.hover {
             self.push_key(
-                if self.current_button {
+                if self.current_rubber {
                     &KeyCode::BTN_TOOL_RUBBER
                 } else {
                     &KeyCode::BTN_TOOL_PEN
@@ -197,8 +314,238 @@ impl StylusBackend {
         }
         self.current_hover = pen_data.hover;
 
-        self.push_msc(MiscCode::MSC_TIMESTAMP.0, pen_data.timestamp);
+        if let Some(serial) = pen_data.tool_serial {
+            self.push_msc(MiscCode::MSC_SERIAL.0, serial as i32);
+        }
+        // MSC_TIMESTAMP is microseconds, same as the finger device
+        self.push_msc(
+            MiscCode::MSC_TIMESTAMP.0,
+            pen_data.timestamp.wrapping_mul(1000),
+        );
         self.device.emit(&self.inputs).err_to_string()?;
         Ok(())
     }
+
+    // Lift the pen and release every pressed button / tool
+    pub fn reset(&mut self) -> Result<(), String> {
+        self.inputs.clear();
+
+        if self.current_down {
+            self.push_abs_event(ABS_PRESSURE, 0);
+            self.push_key(&KeyCode::BTN_TOUCH, 0);
+        }
+        self.deactivate();
+        if self.current_button2 {
+            self.push_key(&KeyCode::BTN_STYLUS2, 0);
+        }
+        if self.current_hover {
+            self.push_key(
+                if self.current_rubber {
+                    &KeyCode::BTN_TOOL_RUBBER
+                } else {
+                    &KeyCode::BTN_TOOL_PEN
+                },
+                0,
+            );
+        }
+
+        self.current_down = false;
+        self.current_hover = false;
+        self.current_rubber = false;
+        self.barrel_timestamp = -1;
+        self.long_press_timestamp = -1;
+        self.last_button = false;
+        self.tap_release = false;
+        self.eraser_hold = false;
+        self.current_button2 = false;
+
+        if !self.inputs.is_empty() {
+            self.device.emit(&self.inputs).err_to_string()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::evdev::sink::CaptureSink;
+    use evdev::EventType;
+
+    // Key events of every emitted frame
+    type KeyFrames = Vec<Vec<(KeyCode, i32)>>;
+
+    const PEN: KeyCode = KeyCode::BTN_TOOL_PEN;
+    const RUBBER: KeyCode = KeyCode::BTN_TOOL_RUBBER;
+    const TOUCH: KeyCode = KeyCode::BTN_TOUCH;
+    const STYLUS: KeyCode = KeyCode::BTN_STYLUS;
+    const STYLUS2: KeyCode = KeyCode::BTN_STYLUS2;
+
+    fn pen(hover: bool, down: bool, button: bool, eraser: bool, timestamp: i32) -> Stylus {
+        Stylus {
+            down,
+            button,
+            hover,
+            button2: false,
+            eraser,
+            pressure: if down { 100 } else { 0 },
+            tilt_x: 0,
+            tilt_y: 0,
+            x: 10,
+            y: 10,
+            timestamp,
+            tool_serial: None,
+        }
+    }
+
+    fn run(samples: &[Stylus], barrel: &BarrelConfig) -> KeyFrames {
+        let sink = CaptureSink::new();
+        let mut backend = StylusBackend::from_sink(Box::new(sink.clone()));
+        for sample in samples {
+            backend.process(sample, barrel).unwrap();
+        }
+        sink.take()
+            .iter()
+            .map(|frame| {
+                frame
+                    .iter()
+                    .filter(|event| event.event_type() == EventType::KEY)
+                    .map(|event| (KeyCode::new(event.code()), event.value()))
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn eraser_switching() {
+        let cases: &[(&str, Vec<Stylus>, KeyFrames)] = &[
+            (
+                "pen enters hover",
+                vec![pen(true, false, false, false, 0)],
+                vec![vec![(PEN, 1)]],
+            ),
+            (
+                "eraser end enters hover",
+                vec![pen(true, false, false, true, 0)],
+                vec![vec![(RUBBER, 1)]],
+            ),
+            (
+                "eraser end enters while down",
+                vec![pen(true, true, false, true, 0)],
+                vec![vec![(RUBBER, 1), (TOUCH, 1)]],
+            ),
+            (
+                "flip while hovering",
+                vec![
+                    pen(true, false, false, false, 0),
+                    pen(true, false, false, true, 10),
+                ],
+                vec![vec![(PEN, 1)], vec![(PEN, 0)], vec![(RUBBER, 1)]],
+            ),
+            (
+                "flip waits for the pen lift",
+                vec![
+                    pen(true, false, false, false, 0),
+                    pen(true, true, false, false, 10),
+                    pen(true, true, false, true, 20),
+                    pen(true, false, false, true, 30),
+                ],
+                vec![
+                    vec![(PEN, 1)],
+                    vec![(TOUCH, 1)],
+                    vec![],
+                    vec![(TOUCH, 0), (PEN, 0)],
+                    vec![(RUBBER, 1)],
+                ],
+            ),
+            (
+                "eraser leaves hover",
+                vec![
+                    pen(true, false, false, true, 0),
+                    pen(false, false, false, false, 10),
+                ],
+                vec![vec![(RUBBER, 1)], vec![(RUBBER, 0)]],
+            ),
+        ];
+
+        for (name, samples, expected) in cases {
+            assert_eq!(&run(samples, &BarrelConfig::default()), expected, "{name}");
+        }
+    }
+
+    #[test]
+    fn barrel_clicks() {
+        let click = BarrelConfig {
+            press: Action::Stylus,
+            double_press: Action::Stylus2,
+            long_press: Action::Off,
+            ..BarrelConfig::default()
+        };
+        let long = BarrelConfig {
+            long_press: Action::Stylus2,
+            ..click.clone()
+        };
+        // (button, timestamp) after entering hover at 0
+        type Buttons<'a> = &'a [(bool, i32)];
+        let cases: &[(&str, &BarrelConfig, Buttons, KeyFrames)] = &[
+            (
+                "single click",
+                &click,
+                &[(true, 100), (false, 150)],
+                vec![vec![(PEN, 1)], vec![(STYLUS, 1)], vec![(STYLUS, 0)]],
+            ),
+            (
+                "double click",
+                &click,
+                &[(true, 100), (false, 150), (true, 300), (false, 350)],
+                vec![
+                    vec![(PEN, 1)],
+                    vec![(STYLUS, 1)],
+                    vec![(STYLUS, 0)],
+                    vec![(STYLUS2, 1)],
+                    vec![(STYLUS2, 0)],
+                ],
+            ),
+            (
+                "second click after the timeout",
+                &click,
+                &[(true, 100), (false, 150), (true, 1000), (false, 1050)],
+                vec![
+                    vec![(PEN, 1)],
+                    vec![(STYLUS, 1)],
+                    vec![(STYLUS, 0)],
+                    vec![(STYLUS, 1)],
+                    vec![(STYLUS, 0)],
+                ],
+            ),
+            (
+                "tap while a long press is possible",
+                &long,
+                &[(true, 100), (false, 150), (false, 160)],
+                vec![vec![(PEN, 1)], vec![], vec![(STYLUS, 1)], vec![(STYLUS, 0)]],
+            ),
+            (
+                "long press",
+                &long,
+                &[(true, 100), (true, 700), (false, 750)],
+                vec![
+                    vec![(PEN, 1)],
+                    vec![],
+                    vec![(STYLUS2, 1)],
+                    vec![(STYLUS2, 0)],
+                ],
+            ),
+        ];
+
+        for (name, barrel, buttons, expected) in cases {
+            let samples = std::iter::once(pen(true, false, false, false, 0))
+                .chain(
+                    buttons
+                        .iter()
+                        .map(|(button, timestamp)| pen(true, false, *button, false, *timestamp)),
+                )
+                .collect::<Vec<_>>();
+            assert_eq!(&run(&samples, barrel), expected, "{name}");
+        }
+    }
 }
@@ -0,0 +1,465 @@
+use std::{collections::BTreeMap, fmt};
+
+use crate::{
+    parse::{Finger, Init},
+    profile::{GestureConfig, Profile},
+};
+
+// MT slot 입력으로부터 탭, 핀치, 회전, 스와이프, 길게 누르기 제스처를 인식합니다
+// 시간은 이벤트의 timestamp (ms) 로 계산하며, 손가락을 움직이지 않는 동안에는 tick 으로 길게 누르기를 확인합니다
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Direction {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Gesture {
+    Tap(usize),
+    LongPress(usize),
+    Swipe(usize, Direction),
+    PinchIn,
+    PinchOut,
+    RotateCw,
+    RotateCcw,
+}
+
+// Names used as keys of the gesture action table
+impl fmt::Display for Gesture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Gesture::Tap(fingers) => write!(f, "tap-{}", fingers),
+            Gesture::LongPress(fingers) => write!(f, "long-press-{}", fingers),
+            Gesture::Swipe(fingers, direction) => write!(
+                f,
+                "swipe-{}-{}",
+                fingers,
+                match direction {
+                    Direction::Up => "up",
+                    Direction::Down => "down",
+                    Direction::Left => "left",
+                    Direction::Right => "right",
+                }
+            ),
+            Gesture::PinchIn => write!(f, "pinch-in"),
+            Gesture::PinchOut => write!(f, "pinch-out"),
+            Gesture::RotateCw => write!(f, "rotate-cw"),
+            Gesture::RotateCcw => write!(f, "rotate-ccw"),
+        }
+    }
+}
+
+struct Contact {
+    start: (f32, f32),
+    current: (f32, f32),
+}
+
+// Distance and angle between the first two contacts
+struct TwoFinger {
+    distance: f32,
+    angle: f32,
+}
+
+pub struct GestureRecognizer {
+    config: GestureConfig,
+    size: (f32, f32),
+    contacts: BTreeMap<u8, Contact>,
+    // Event timestamp of the first contact, milliseconds
+    started_at: i32,
+    max_fingers: usize,
+    moved: bool,
+    // Pinch / rotate happened, no tap or swipe at the end
+    transformed: bool,
+    long_pressed: bool,
+    // Sum of the displacement of lifted contacts
+    lifted_delta: (f32, f32),
+    lifted_count: usize,
+    baseline: Option<TwoFinger>,
+}
+
+impl GestureRecognizer {
+    pub fn new(config: GestureConfig, width: u16, height: u16) -> Self {
+        Self {
+            config,
+            size: (width.max(1) as f32, height.max(1) as f32),
+            contacts: BTreeMap::new(),
+            started_at: 0,
+            max_fingers: 0,
+            moved: false,
+            transformed: false,
+            long_pressed: false,
+            lifted_delta: (0.0, 0.0),
+            lifted_count: 0,
+            baseline: None,
+        }
+    }
+
+    // Only when the profile maps any gesture
+    pub fn from_profile(profile: &Profile, init_data: &Init) -> Option<Self> {
+        if profile.gestures.actions.is_empty() {
+            return None;
+        }
+        Some(Self::new(
+            profile.gestures.clone(),
+            init_data.width,
+            init_data.height,
+        ))
+    }
+
+    fn two_finger(&self) -> Option<TwoFinger> {
+        let mut iter = self.contacts.values();
+        let (a, b) = (iter.next()?.current, iter.next()?.current);
+        let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+        Some(TwoFinger {
+            distance: dx.hypot(dy),
+            angle: dy.atan2(dx).to_degrees(),
+        })
+    }
+
+    // Fire pinch / rotate steps against the baseline
+    fn process_transform(&mut self, gestures: &mut Vec<Gesture>) {
+        let (Some(current), Some(baseline)) = (self.two_finger(), self.baseline.as_mut()) else {
+            return;
+        };
+
+        let scale = current.distance / baseline.distance.max(1.0);
+        if scale >= 1.0 + self.config.pinch_step {
+            gestures.push(Gesture::PinchOut);
+            baseline.distance = current.distance;
+            self.transformed = true;
+        } else if scale <= 1.0 / (1.0 + self.config.pinch_step) {
+            gestures.push(Gesture::PinchIn);
+            baseline.distance = current.distance;
+            self.transformed = true;
+        }
+
+        let mut rotation = current.angle - baseline.angle;
+        if rotation > 180.0 {
+            rotation -= 360.0;
+        } else if rotation < -180.0 {
+            rotation += 360.0;
+        }
+        // Y axis goes down, so a positive angle is clockwise
+        if rotation.abs() >= self.config.rotate_step {
+            gestures.push(if rotation > 0.0 {
+                Gesture::RotateCw
+            } else {
+                Gesture::RotateCcw
+            });
+            baseline.angle = current.angle;
+            self.transformed = true;
+        }
+    }
+
+    // Milliseconds since the gesture started, timestamps may wrap around
+    fn elapsed(&self, timestamp: i32) -> u64 {
+        timestamp.wrapping_sub(self.started_at).max(0) as u64
+    }
+
+    fn is_long_press(&self, timestamp: i32) -> bool {
+        !self.moved
+            && !self.transformed
+            && !self.long_pressed
+            && self.elapsed(timestamp) >= self.config.long_press_timeout
+    }
+
+    // Gesture finished, every finger lifted
+    fn finish(&mut self, gestures: &mut Vec<Gesture>, timestamp: i32) {
+        if self.is_long_press(timestamp) {
+            gestures.push(Gesture::LongPress(self.max_fingers));
+        } else if !self.moved && !self.transformed && !self.long_pressed {
+            if self.elapsed(timestamp) < self.config.tap_timeout {
+                gestures.push(Gesture::Tap(self.max_fingers));
+            }
+        } else if self.moved && !self.transformed && self.lifted_count != 0 {
+            let dx = self.lifted_delta.0 / self.lifted_count as f32 / self.size.0;
+            let dy = self.lifted_delta.1 / self.lifted_count as f32 / self.size.1;
+            if dx.abs().max(dy.abs()) >= self.config.swipe_distance {
+                let direction = if dx.abs() > dy.abs() {
+                    if dx > 0.0 {
+                        Direction::Right
+                    } else {
+                        Direction::Left
+                    }
+                } else if dy > 0.0 {
+                    Direction::Down
+                } else {
+                    Direction::Up
+                };
+                gestures.push(Gesture::Swipe(self.max_fingers, direction));
+            }
+        }
+    }
+
+    pub fn process(&mut self, touch_data: &Finger, timestamp: i32) -> Vec<Gesture> {
+        let mut gestures = Vec::new();
+        let has_position = touch_data.x != -1 && touch_data.y != -1;
+        let position = (touch_data.x as f32, touch_data.y as f32);
+
+        if touch_data.down {
+            // Wait for the first position of the new contact
+            if !has_position && !self.contacts.contains_key(&touch_data.slot) {
+                return gestures;
+            }
+            if self.contacts.is_empty() {
+                // New gesture
+                self.started_at = timestamp;
+                self.max_fingers = 0;
+                self.moved = false;
+                self.transformed = false;
+                self.long_pressed = false;
+                self.lifted_delta = (0.0, 0.0);
+                self.lifted_count = 0;
+            }
+
+            let contact = self.contacts.entry(touch_data.slot).or_insert(Contact {
+                start: position,
+                current: position,
+            });
+            if has_position {
+                contact.current = position;
+                let (dx, dy) = (
+                    contact.current.0 - contact.start.0,
+                    contact.current.1 - contact.start.1,
+                );
+                if dx.hypot(dy) > self.config.tap_distance {
+                    self.moved = true;
+                }
+            }
+
+            if self.contacts.len() > self.max_fingers {
+                self.max_fingers = self.contacts.len();
+                self.baseline = self.two_finger();
+            }
+            self.process_transform(&mut gestures);
+            gestures.extend(self.tick(timestamp));
+        } else if let Some(contact) = self.contacts.remove(&touch_data.slot) {
+            let current = if has_position {
+                position
+            } else {
+                contact.current
+            };
+            self.lifted_delta.0 += current.0 - contact.start.0;
+            self.lifted_delta.1 += current.1 - contact.start.1;
+            self.lifted_count += 1;
+            self.baseline = self.two_finger();
+
+            if self.contacts.is_empty() {
+                self.finish(&mut gestures, timestamp);
+            }
+        }
+
+        gestures
+    }
+
+    // Fire the long press of contacts held still, called on every frame and by a timer
+    pub fn tick(&mut self, timestamp: i32) -> Option<Gesture> {
+        if self.contacts.is_empty() || !self.is_long_press(timestamp) {
+            return None;
+        }
+        self.long_pressed = true;
+        Some(Gesture::LongPress(self.max_fingers))
+    }
+
+    // Forget the current gesture without firing anything
+    pub fn reset(&mut self) {
+        self.contacts.clear();
+        self.baseline = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    enum Step {
+        // slot, x, y, timestamp
+        Down(u8, i16, i16, i32),
+        Up(u8, i32),
+        Tick(i32),
+        Reset,
+    }
+    use Step::*;
+
+    fn run(steps: &[Step]) -> Vec<Gesture> {
+        let mut recognizer = GestureRecognizer::new(GestureConfig::default(), 1000, 1000);
+        let finger = |slot, down, x, y| Finger {
+            slot,
+            down,
+            total_down: 0,
+            tracking_id: slot as i32,
+            x,
+            y,
+        };
+        let mut gestures = Vec::new();
+        for step in steps {
+            match *step {
+                Down(slot, x, y, timestamp) => {
+                    gestures.extend(recognizer.process(&finger(slot, true, x, y), timestamp))
+                }
+                Up(slot, timestamp) => {
+                    gestures.extend(recognizer.process(&finger(slot, false, -1, -1), timestamp))
+                }
+                Tick(timestamp) => gestures.extend(recognizer.tick(timestamp)),
+                Reset => recognizer.reset(),
+            }
+        }
+        gestures
+    }
+
+    #[test]
+    fn gestures() {
+        let cases: &[(&str, Vec<Step>, Vec<Gesture>)] = &[
+            (
+                "tap-1",
+                vec![Down(0, 100, 100, 0), Up(0, 100)],
+                vec![Gesture::Tap(1)],
+            ),
+            (
+                "tap-2",
+                vec![
+                    Down(0, 100, 100, 0),
+                    Down(1, 300, 100, 10),
+                    Up(0, 100),
+                    Up(1, 110),
+                ],
+                vec![Gesture::Tap(2)],
+            ),
+            (
+                "tap-3",
+                vec![
+                    Down(0, 100, 100, 0),
+                    Down(1, 300, 100, 0),
+                    Down(2, 500, 100, 20),
+                    Up(0, 100),
+                    Up(1, 100),
+                    Up(2, 120),
+                ],
+                vec![Gesture::Tap(3)],
+            ),
+            (
+                "too slow for a tap",
+                vec![Down(0, 100, 100, 0), Up(0, 400)],
+                vec![],
+            ),
+            (
+                "long press held still",
+                vec![
+                    Down(0, 100, 100, 0),
+                    Tick(300),
+                    Tick(650),
+                    Tick(700),
+                    Up(0, 800),
+                ],
+                vec![Gesture::LongPress(1)],
+            ),
+            (
+                "long press on the next frame",
+                vec![Down(0, 100, 100, 0), Down(0, 105, 100, 650), Up(0, 700)],
+                vec![Gesture::LongPress(1)],
+            ),
+            (
+                "long press across the timestamp wrap",
+                vec![Down(0, 100, 100, i32::MAX - 100), Tick(i32::MIN + 600)],
+                vec![Gesture::LongPress(1)],
+            ),
+            (
+                "moved contact is no long press",
+                vec![Down(0, 100, 100, 0), Down(0, 200, 100, 100), Tick(700)],
+                vec![],
+            ),
+            (
+                "swipe up",
+                vec![Down(0, 500, 500, 0), Down(0, 500, 200, 50), Up(0, 100)],
+                vec![Gesture::Swipe(1, Direction::Up)],
+            ),
+            (
+                "swipe down",
+                vec![Down(0, 500, 500, 0), Down(0, 500, 800, 50), Up(0, 100)],
+                vec![Gesture::Swipe(1, Direction::Down)],
+            ),
+            (
+                "swipe left",
+                vec![Down(0, 500, 500, 0), Down(0, 200, 500, 50), Up(0, 100)],
+                vec![Gesture::Swipe(1, Direction::Left)],
+            ),
+            (
+                "swipe right",
+                vec![Down(0, 500, 500, 0), Down(0, 800, 500, 50), Up(0, 100)],
+                vec![Gesture::Swipe(1, Direction::Right)],
+            ),
+            (
+                "pinch out",
+                vec![
+                    Down(0, 400, 500, 0),
+                    Down(1, 600, 500, 0),
+                    Down(1, 700, 500, 50),
+                    Up(0, 100),
+                    Up(1, 100),
+                ],
+                vec![Gesture::PinchOut],
+            ),
+            (
+                "pinch in",
+                vec![
+                    Down(0, 400, 500, 0),
+                    Down(1, 600, 500, 0),
+                    Down(1, 520, 500, 50),
+                    Up(0, 100),
+                    Up(1, 100),
+                ],
+                vec![Gesture::PinchIn],
+            ),
+            (
+                "rotate cw",
+                vec![
+                    Down(0, 400, 500, 0),
+                    Down(1, 600, 500, 0),
+                    Down(1, 600, 560, 50),
+                    Up(0, 100),
+                    Up(1, 100),
+                ],
+                vec![Gesture::RotateCw],
+            ),
+            (
+                "rotate ccw",
+                vec![
+                    Down(0, 400, 500, 0),
+                    Down(1, 600, 500, 0),
+                    Down(1, 600, 440, 50),
+                    Up(0, 100),
+                    Up(1, 100),
+                ],
+                vec![Gesture::RotateCcw],
+            ),
+            (
+                "reset before the lift",
+                vec![Down(0, 100, 100, 0), Reset, Up(0, 100)],
+                vec![],
+            ),
+            (
+                "reset while held still",
+                vec![Down(0, 100, 100, 0), Reset, Tick(700)],
+                vec![],
+            ),
+            (
+                "tap after a reset",
+                vec![
+                    Down(0, 100, 100, 0),
+                    Down(0, 400, 100, 50),
+                    Reset,
+                    Down(0, 100, 100, 1000),
+                    Up(0, 1100),
+                ],
+                vec![Gesture::Tap(1)],
+            ),
+        ];
+
+        for (name, steps, expected) in cases {
+            assert_eq!(&run(steps), expected, "{name}");
+        }
+    }
+}
@@ -0,0 +1,180 @@
+use evdev::KeyCode;
+
+// Android keycode 와 문자를 linux 키 코드로 변환하는 기본 테이블.
+// profile 의 keyboard.keymap / keyboard.layout 이 우선합니다.
+
+// Android KeyEvent keycode -> linux key
+pub fn android_keycode(code: u16) -> Option<KeyCode> {
+    const LETTERS: [KeyCode; 26] = [
+        KeyCode::KEY_A,
+        KeyCode::KEY_B,
+        KeyCode::KEY_C,
+        KeyCode::KEY_D,
+        KeyCode::KEY_E,
+        KeyCode::KEY_F,
+        KeyCode::KEY_G,
+        KeyCode::KEY_H,
+        KeyCode::KEY_I,
+        KeyCode::KEY_J,
+        KeyCode::KEY_K,
+        KeyCode::KEY_L,
+        KeyCode::KEY_M,
+        KeyCode::KEY_N,
+        KeyCode::KEY_O,
+        KeyCode::KEY_P,
+        KeyCode::KEY_Q,
+        KeyCode::KEY_R,
+        KeyCode::KEY_S,
+        KeyCode::KEY_T,
+        KeyCode::KEY_U,
+        KeyCode::KEY_V,
+        KeyCode::KEY_W,
+        KeyCode::KEY_X,
+        KeyCode::KEY_Y,
+        KeyCode::KEY_Z,
+    ];
+    const DIGITS: [KeyCode; 10] = [
+        KeyCode::KEY_0,
+        KeyCode::KEY_1,
+        KeyCode::KEY_2,
+        KeyCode::KEY_3,
+        KeyCode::KEY_4,
+        KeyCode::KEY_5,
+        KeyCode::KEY_6,
+        KeyCode::KEY_7,
+        KeyCode::KEY_8,
+        KeyCode::KEY_9,
+    ];
+    const FUNCTIONS: [KeyCode; 12] = [
+        KeyCode::KEY_F1,
+        KeyCode::KEY_F2,
+        KeyCode::KEY_F3,
+        KeyCode::KEY_F4,
+        KeyCode::KEY_F5,
+        KeyCode::KEY_F6,
+        KeyCode::KEY_F7,
+        KeyCode::KEY_F8,
+        KeyCode::KEY_F9,
+        KeyCode::KEY_F10,
+        KeyCode::KEY_F11,
+        KeyCode::KEY_F12,
+    ];
+
+    Some(match code {
+        7..=16 => DIGITS[code as usize - 7],
+        29..=54 => LETTERS[code as usize - 29],
+        131..=142 => FUNCTIONS[code as usize - 131],
+        19 => KeyCode::KEY_UP,
+        20 => KeyCode::KEY_DOWN,
+        21 => KeyCode::KEY_LEFT,
+        22 => KeyCode::KEY_RIGHT,
+        24 => KeyCode::KEY_VOLUMEUP,
+        25 => KeyCode::KEY_VOLUMEDOWN,
+        55 => KeyCode::KEY_COMMA,
+        56 => KeyCode::KEY_DOT,
+        57 => KeyCode::KEY_LEFTALT,
+        58 => KeyCode::KEY_RIGHTALT,
+        59 => KeyCode::KEY_LEFTSHIFT,
+        60 => KeyCode::KEY_RIGHTSHIFT,
+        61 => KeyCode::KEY_TAB,
+        62 => KeyCode::KEY_SPACE,
+        66 => KeyCode::KEY_ENTER,
+        67 => KeyCode::KEY_BACKSPACE,
+        68 => KeyCode::KEY_GRAVE,
+        69 => KeyCode::KEY_MINUS,
+        70 => KeyCode::KEY_EQUAL,
+        71 => KeyCode::KEY_LEFTBRACE,
+        72 => KeyCode::KEY_RIGHTBRACE,
+        73 => KeyCode::KEY_BACKSLASH,
+        74 => KeyCode::KEY_SEMICOLON,
+        75 => KeyCode::KEY_APOSTROPHE,
+        76 => KeyCode::KEY_SLASH,
+        92 => KeyCode::KEY_PAGEUP,
+        93 => KeyCode::KEY_PAGEDOWN,
+        111 => KeyCode::KEY_ESC,
+        112 => KeyCode::KEY_DELETE,
+        113 => KeyCode::KEY_LEFTCTRL,
+        114 => KeyCode::KEY_RIGHTCTRL,
+        115 => KeyCode::KEY_CAPSLOCK,
+        117 => KeyCode::KEY_LEFTMETA,
+        118 => KeyCode::KEY_RIGHTMETA,
+        122 => KeyCode::KEY_HOME,
+        123 => KeyCode::KEY_END,
+        124 => KeyCode::KEY_INSERT,
+        _ => return None,
+    })
+}
+
+// Character -> keys on the US layout
+pub fn us_layout(character: char) -> Option<Vec<KeyCode>> {
+    const UNSHIFTED: &str = "`1234567890-=qwertyuiop[]\\asdfghjkl;'zxcvbnm,./";
+    const SHIFTED: &str = "~!@#$%^&*()_+QWERTYUIOP{}|ASDFGHJKL:\"ZXCVBNM<>?";
+    const KEYS: [KeyCode; 47] = [
+        KeyCode::KEY_GRAVE,
+        KeyCode::KEY_1,
+        KeyCode::KEY_2,
+        KeyCode::KEY_3,
+        KeyCode::KEY_4,
+        KeyCode::KEY_5,
+        KeyCode::KEY_6,
+        KeyCode::KEY_7,
+        KeyCode::KEY_8,
+        KeyCode::KEY_9,
+        KeyCode::KEY_0,
+        KeyCode::KEY_MINUS,
+        KeyCode::KEY_EQUAL,
+        KeyCode::KEY_Q,
+        KeyCode::KEY_W,
+        KeyCode::KEY_E,
+        KeyCode::KEY_R,
+        KeyCode::KEY_T,
+        KeyCode::KEY_Y,
+        KeyCode::KEY_U,
+        KeyCode::KEY_I,
+        KeyCode::KEY_O,
+        KeyCode::KEY_P,
+        KeyCode::KEY_LEFTBRACE,
+        KeyCode::KEY_RIGHTBRACE,
+        KeyCode::KEY_BACKSLASH,
+        KeyCode::KEY_A,
+        KeyCode::KEY_S,
+        KeyCode::KEY_D,
+        KeyCode::KEY_F,
+        KeyCode::KEY_G,
+        KeyCode::KEY_H,
+        KeyCode::KEY_J,
+        KeyCode::KEY_K,
+        KeyCode::KEY_L,
+        KeyCode::KEY_SEMICOLON,
+        KeyCode::KEY_APOSTROPHE,
+        KeyCode::KEY_Z,
+        KeyCode::KEY_X,
+        KeyCode::KEY_C,
+        KeyCode::KEY_V,
+        KeyCode::KEY_B,
+        KeyCode::KEY_N,
+        KeyCode::KEY_M,
+        KeyCode::KEY_COMMA,
+        KeyCode::KEY_DOT,
+        KeyCode::KEY_SLASH,
+    ];
+
+    match character {
+        ' ' => return Some(vec![KeyCode::KEY_SPACE]),
+        '\n' => return Some(vec![KeyCode::KEY_ENTER]),
+        '\t' => return Some(vec![KeyCode::KEY_TAB]),
+        _ => {}
+    }
+    if let Some(index) = UNSHIFTED.chars().position(|c| c == character) {
+        return Some(vec![KEYS[index]]);
+    }
+    SHIFTED
+        .chars()
+        .position(|c| c == character)
+        .map(|index| vec![KeyCode::KEY_LEFTSHIFT, KEYS[index]])
+}
+
+// Keys typing a hex digit, used by the unicode input sequence
+pub fn hex_digit(digit: char) -> Option<KeyCode> {
+    us_layout(digit.to_ascii_lowercase()).and_then(|keys| keys.first().copied())
+}
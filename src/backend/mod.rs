@@ -1,11 +1,39 @@
 mod evdev;
+mod gesture;
+mod keymap;
+mod pool;
+mod tilt;
 
 #[derive(Clone)]
 pub struct BackendConfig {
+    pub evdev_vendor_id: u16,
+    pub evdev_stylus_product_id: u16,
+    pub evdev_trackpad_product_id: u16,
+    pub evdev_keyboard_product_id: u16,
+    pub evdev_touchscreen_product_id: u16,
     pub evdev_trackpad_fuzz: i32,
     pub evdev_trackpad_res: i32,
     pub evdev_trackpad_flat: i32,
 }
 
+// Identifies the tablet behind a backend, used to name the virtual devices
+#[derive(Clone)]
+pub struct DeviceIdentity {
+    pub serial: String,
+    pub label: String,
+}
+
+impl DeviceIdentity {
+    pub fn new(serial: &str, profile: Option<&str>) -> Self {
+        Self {
+            serial: serial.to_string(),
+            label: profile.unwrap_or(serial).to_string(),
+        }
+    }
+}
+
 #[cfg(target_os = "linux")]
-pub use evdev::InputBackend;
+pub use evdev::{
+    CaptureSink, EventSink, FingerBackend, InputBackend, MergeRegistry, StylusBackend,
+};
+pub use pool::{BackendPool, run_backend_sweeper};
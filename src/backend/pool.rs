@@ -0,0 +1,52 @@
+use std::{collections::HashMap, sync::Arc};
+
+use qwreey_utility_rs::RwMap;
+use tokio::{
+    task::JoinHandle,
+    time::{Duration, Instant, sleep},
+};
+
+use super::InputBackend;
+use crate::cli::Command;
+
+// 연결이 끊긴 기기의 backend 를 보관해 재연결시 같은 uinput 장치를 재사용합니다
+
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Default)]
+pub struct BackendPool {
+    idle: HashMap<String, (InputBackend, Instant)>,
+}
+
+impl BackendPool {
+    // Take the idle backend of the device, if any
+    pub fn take(&mut self, serial: &str) -> Option<InputBackend> {
+        self.idle.remove(serial).map(|(backend, _)| backend)
+    }
+
+    // Keep the backend alive until the device comes back or the idle timeout elapses
+    pub fn release(&mut self, serial: String, backend: InputBackend) {
+        self.idle.insert(serial, (backend, Instant::now()));
+    }
+
+    // Destroy backends idle for longer than the timeout
+    pub fn sweep(&mut self, timeout: Duration) {
+        self.idle.retain(|serial, (_, released_at)| {
+            let alive = released_at.elapsed() < timeout;
+            if !alive {
+                tracing::info!("Destroying idle input backend of {}", serial);
+            }
+            alive
+        });
+    }
+}
+
+pub fn run_backend_sweeper(userdata: Arc<RwMap>) -> JoinHandle<()> {
+    let timeout = Duration::from_secs(userdata.get_of::<Command>().unwrap().backend_idle_timeout);
+    tokio::spawn(async move {
+        loop {
+            sleep(SWEEP_INTERVAL).await;
+            userdata.get_of_mut::<BackendPool>().unwrap().sweep(timeout);
+        }
+    })
+}
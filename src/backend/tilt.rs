@@ -0,0 +1,136 @@
+use std::f32::consts::PI;
+
+use crate::{parse::TiltFormat, profile::TiltConfig};
+
+// 태블릿이 보낸 tilt 를 libinput 이 기대하는 X / Y tilt (centidegrees) 로 변환합니다
+
+fn to_radians(centidegrees: i16) -> f32 {
+    centidegrees as f32 / 18000.0 * PI
+}
+
+fn to_centidegrees(radians: f32) -> f32 {
+    radians / PI * 18000.0
+}
+
+// Orientation / tilt from the screen normal -> tilt along X / Y
+fn polar_to_xy(orientation: i16, tilt: i16) -> (f32, f32) {
+    let (orientation, tilt) = (to_radians(orientation), to_radians(tilt));
+    // Direction the pen leans to, X goes right and Y goes down
+    let (lean_x, lean_y) = (orientation.sin(), -orientation.cos());
+    let (sin, cos) = tilt.sin_cos();
+    (
+        to_centidegrees((lean_x * sin).atan2(cos)),
+        to_centidegrees((lean_y * sin).atan2(cos)),
+    )
+}
+
+pub fn convert(
+    config: &TiltConfig,
+    format: TiltFormat,
+    max: i16,
+    tilt_x: i16,
+    tilt_y: i16,
+) -> (i16, i16) {
+    let (mut x, mut y) = match config.format.unwrap_or(format) {
+        TiltFormat::Xy => (tilt_x as f32, tilt_y as f32),
+        TiltFormat::Polar => polar_to_xy(tilt_x, tilt_y),
+    };
+    if config.invert_x {
+        x = -x;
+    }
+    if config.invert_y {
+        y = -y;
+    }
+    let apply = |value: f32, offset: f32| {
+        (value + offset * 100.0)
+            .round()
+            .clamp(-max as f32, max as f32) as i16
+    };
+    (apply(x, config.offset_x), apply(y, config.offset_y))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAX: i16 = 9000;
+
+    fn polar(orientation: i16, tilt: i16) -> (i16, i16) {
+        convert(
+            &TiltConfig::default(),
+            TiltFormat::Polar,
+            MAX,
+            orientation,
+            tilt,
+        )
+    }
+
+    #[test]
+    fn polar_known_points() {
+        let cases = [
+            // (orientation, tilt), (x, y)
+            ((0, 0), (0, 0)),
+            ((9000, 0), (0, 0)),
+            // Leaning up, right, down and left at 45 degrees
+            ((0, 4500), (0, -4500)),
+            ((9000, 4500), (4500, 0)),
+            ((18000, 4500), (0, 4500)),
+            ((-18000, 4500), (0, 4500)),
+            ((-9000, 4500), (-4500, 0)),
+            // Lying flat towards the upper right
+            ((4500, 9000), (9000, -9000)),
+        ];
+        for ((orientation, tilt), expected) in cases {
+            assert_eq!(polar(orientation, tilt), expected, "{orientation} {tilt}");
+        }
+    }
+
+    #[test]
+    fn offset_and_invert_after_conversion() {
+        let config = TiltConfig {
+            offset_x: 5.0,
+            offset_y: -10.0,
+            invert_x: true,
+            ..Default::default()
+        };
+        // Polar (4500, 0) is inverted to (-4500, 0), then offset
+        assert_eq!(
+            convert(&config, TiltFormat::Polar, MAX, 9000, 4500),
+            (-4000, -1000)
+        );
+        assert_eq!(
+            convert(&config, TiltFormat::Xy, MAX, 2000, 3000),
+            (-1500, 2000)
+        );
+
+        // The profile format overrides the declared one
+        let config = TiltConfig {
+            format: Some(TiltFormat::Polar),
+            ..Default::default()
+        };
+        assert_eq!(convert(&config, TiltFormat::Xy, MAX, 9000, 4500), (4500, 0));
+    }
+
+    #[test]
+    fn stays_within_tilt_max() {
+        let config = TiltConfig {
+            offset_x: 30.0,
+            offset_y: -30.0,
+            ..Default::default()
+        };
+        for max in [MAX, 6000] {
+            for format in [TiltFormat::Xy, TiltFormat::Polar] {
+                for first in (-18000..=18000).step_by(1500) {
+                    for second in (-9000..=9000).step_by(1500) {
+                        let (x, y) = convert(&config, format, max, first, second);
+                        assert!(x.abs() <= max && y.abs() <= max, "{first} {second}");
+                    }
+                }
+            }
+        }
+        assert_eq!(
+            convert(&config, TiltFormat::Xy, 6000, 5000, -5000),
+            (6000, -6000)
+        );
+    }
+}
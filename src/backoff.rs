@@ -0,0 +1,140 @@
+use std::{collections::HashMap, time::Duration};
+
+use tokio::time::Instant;
+
+// 재연결 루프의 대기 시간 (지수 증가, 상한, jitter) 과
+// control socket 의 status 명령으로 보여줄 연결 상태.
+
+#[derive(Clone, Copy)]
+pub struct BackoffConfig {
+    pub initial: Duration,
+    pub max: Duration,
+    // Fraction of the delay added or removed at random
+    pub jitter: f32,
+}
+
+impl BackoffConfig {
+    pub fn new(initial_ms: u64, max_ms: u64, jitter: f32) -> Result<Self, String> {
+        if initial_ms == 0 || initial_ms > max_ms {
+            return Err(String::from("Retry delays must satisfy 0 < initial <= max"));
+        }
+        if !(0.0..=1.0).contains(&jitter) {
+            return Err(String::from("Retry jitter must be between 0 and 1"));
+        }
+        Ok(Self {
+            initial: Duration::from_millis(initial_ms),
+            max: Duration::from_millis(max_ms),
+            jitter,
+        })
+    }
+}
+
+pub struct Backoff {
+    config: BackoffConfig,
+    attempt: u32,
+}
+
+impl Backoff {
+    pub fn new(config: BackoffConfig) -> Self {
+        Self { config, attempt: 0 }
+    }
+
+    // Start again from the initial delay once connected
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+
+    pub fn attempt(&self) -> u32 {
+        self.attempt
+    }
+
+    // Delay before the next attempt, doubled each time up to the max
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self
+            .config
+            .initial
+            .saturating_mul(1u32 << self.attempt.min(31))
+            .min(self.config.max);
+        self.attempt = self.attempt.saturating_add(1);
+        let spread = (fastrand::f32() * 2.0 - 1.0) * self.config.jitter;
+        delay.mul_f32(1.0 + spread)
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum LinkStatus {
+    Connected,
+    Retrying { at: Instant, attempt: u32 },
+}
+
+impl LinkStatus {
+    pub fn retrying(delay: Duration, attempt: u32) -> Self {
+        LinkStatus::Retrying {
+            at: Instant::now() + delay,
+            attempt,
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            LinkStatus::Connected => String::from("connected"),
+            LinkStatus::Retrying { at, attempt } => format!(
+                "retrying:{:.1}s:{}",
+                at.saturating_duration_since(Instant::now()).as_secs_f32(),
+                attempt
+            ),
+        }
+    }
+}
+
+// Connection state of the adb tracker and of every device worker
+#[derive(Default)]
+pub struct Status {
+    pub adb: Option<LinkStatus>,
+    pub devices: HashMap<String, LinkStatus>,
+}
+
+impl Status {
+    pub fn summary(&self) -> String {
+        // No adb entry before the first attempt, or with --no-adb
+        let mut entries = self
+            .adb
+            .iter()
+            .map(|status| format!("adb={}", status.describe()))
+            .collect::<Vec<_>>();
+        let mut devices = self.devices.iter().collect::<Vec<_>>();
+        devices.sort_by(|a, b| a.0.cmp(b.0));
+        for (serial, status) in devices {
+            entries.push(format!("{}={}", serial, status.describe()));
+        }
+        entries.join(" ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delay_doubles_up_to_max() {
+        let config = BackoffConfig::new(100, 1000, 0.0).unwrap();
+        let mut backoff = Backoff::new(config);
+        let delays = (0..6)
+            .map(|_| backoff.next_delay().as_millis())
+            .collect::<Vec<_>>();
+        assert_eq!(delays, [100, 200, 400, 800, 1000, 1000]);
+
+        backoff.reset();
+        assert_eq!(backoff.next_delay().as_millis(), 100);
+    }
+
+    #[test]
+    fn jitter_stays_in_range() {
+        let config = BackoffConfig::new(1000, 1000, 0.2).unwrap();
+        let mut backoff = Backoff::new(config);
+        for _ in 0..100 {
+            let delay = backoff.next_delay().as_millis();
+            assert!((800..=1200).contains(&delay), "{delay}");
+        }
+    }
+}
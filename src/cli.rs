@@ -1,11 +1,22 @@
+use std::{net::SocketAddrV4, path::PathBuf};
+
 use qwreey_utility_rs::{ErrToString, HeadingError};
 
 #[derive(clap::Parser, Clone)]
 #[command(version, about)]
 pub struct Command {
+    #[command(subcommand)]
+    pub subcommand: Option<Subcommand>,
     #[arg(short, long, num_args = 1.., value_parser = parse_device)]
     pub devices: Vec<Device>,
     #[arg(short, long)]
+    pub config: Option<PathBuf>,
+    #[arg(long)]
+    pub control_socket: Option<PathBuf>,
+    // Serve the D-Bus interface on the session bus
+    #[arg(long)]
+    pub dbus: bool,
+    #[arg(short, long)]
     pub verbose: bool,
     #[arg(long)]
     pub daemon: bool,
@@ -21,6 +32,33 @@ pub struct Command {
     pub connected_command: Option<String>,
     #[arg(long)]
     pub disconnected_command: Option<String>,
+    // Directory receiving a trace of every connection
+    #[arg(long)]
+    pub record_traces: Option<PathBuf>,
+    #[arg(long, default_value = "30")]
+    pub backend_idle_timeout: u64,
+    #[arg(long, default_value = "127.0.0.1:5037")]
+    pub adb_server: SocketAddrV4,
+    // Reconnect delays in milliseconds, doubled after each failure
+    #[arg(long, default_value = "1000")]
+    pub retry_initial: u64,
+    #[arg(long, default_value = "30000")]
+    pub retry_max: u64,
+    #[arg(long, default_value = "0.2")]
+    pub retry_jitter: f32,
+    // Connect to the device ports directly, without adb (simulate for example)
+    #[arg(long)]
+    pub no_adb: bool,
+    #[arg(long, default_value = "0")]
+    pub evdev_vendor_id: u16,
+    #[arg(long, default_value = "1332")]
+    pub evdev_stylus_product_id: u16,
+    #[arg(long, default_value = "1333")]
+    pub evdev_trackpad_product_id: u16,
+    #[arg(long, default_value = "1334")]
+    pub evdev_keyboard_product_id: u16,
+    #[arg(long, default_value = "1335")]
+    pub evdev_touchscreen_product_id: u16,
     #[arg(long, default_value = "2")]
     pub evdev_trackpad_fuzz: i32,
     #[arg(long, default_value = "11")]
@@ -29,7 +67,43 @@ pub struct Command {
     pub evdev_trackpad_flat: i32,
 }
 
-const PARSE_ERROR: &str = "The device argument must be provided in the DeviceName:port format";
+#[derive(clap::Subcommand, Clone)]
+pub enum Subcommand {
+    // Serve a fake tablet on a local WebSocket port
+    Simulate(SimulateArgs),
+}
+
+#[derive(clap::Args, Clone)]
+pub struct SimulateArgs {
+    #[arg(short, long, default_value = "23227")]
+    pub port: u16,
+    #[arg(long, value_enum, default_value = "all")]
+    pub stroke: Stroke,
+    // Trace to replay instead of the synthetic strokes
+    #[arg(long)]
+    pub script: Option<PathBuf>,
+    // Milliseconds between messages
+    #[arg(long, default_value = "8")]
+    pub interval: u64,
+    #[arg(long, default_value = "2000")]
+    pub width: u16,
+    #[arg(long, default_value = "1200")]
+    pub height: u16,
+    #[arg(long)]
+    pub repeat: bool,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy)]
+pub enum Stroke {
+    Line,
+    Circle,
+    Pressure,
+    Pinch,
+    All,
+}
+
+const PARSE_ERROR: &str =
+    "The device argument must be provided in the DeviceName:port or DeviceName:port:profile format";
 
 fn parse_device(arg: &str) -> Result<Device, String> {
     let mut split = arg.split(':');
@@ -46,9 +120,15 @@ fn parse_device(arg: &str) -> Result<Device, String> {
         return Err("Port number must be greater than 0".to_string());
     }
 
+    let profile = split.next().map(str::to_string);
+    if profile.as_ref().is_some_and(|profile| profile.is_empty()) {
+        return Err("Profile name must not be empty".to_string());
+    }
+
     Ok(Device {
         bind_port: port,
         name,
+        profile,
     })
 }
 
@@ -56,19 +136,19 @@ fn parse_device(arg: &str) -> Result<Device, String> {
 pub struct Device {
     pub bind_port: i32,
     pub name: String,
+    pub profile: Option<String>,
 }
 
 pub type DeviceList = Vec<Device>;
 pub trait DeviceListUtil {
+    fn get_device(&self, id: &str) -> Option<&Device>;
     fn get_port(&self, id: &str) -> Option<i32>;
 }
 impl DeviceListUtil for DeviceList {
+    fn get_device(&self, id: &str) -> Option<&Device> {
+        self.iter().find(|device| device.name == id)
+    }
     fn get_port(&self, id: &str) -> Option<i32> {
-        for device in self {
-            if device.name == id {
-                return Some(device.bind_port);
-            }
-        }
-        None
+        self.get_device(id).map(|device| device.bind_port)
     }
 }
@@ -2,42 +2,105 @@ use std::{str::FromStr, sync::Arc};
 
 use adb_client::DeviceShort;
 use bytebuffer::{ByteReader, Endian};
-use futures_util::StreamExt;
+use futures_util::{SinkExt, StreamExt};
 use http::Uri;
 use notify_rust::Notification;
 use qwreey_utility_rs::RwMap;
 use tokio::{
     io::{AsyncBufReadExt, BufReader},
     process::Command as TokioCommand,
-    time::{Duration, Instant, sleep},
+    sync::mpsc::unbounded_channel,
+    time::{Duration, Instant, MissedTickBehavior, interval, sleep},
 };
-use tokio_websockets::ClientBuilder;
+use tokio_websockets::{ClientBuilder, Message};
 
 use crate::{
     WorkerIdMap,
-    backend::{BackendConfig, InputBackend},
+    backend::{BackendConfig, BackendPool, DeviceIdentity, InputBackend, MergeRegistry},
+    backoff::{Backoff, BackoffConfig, LinkStatus, Status},
     cli::Command,
-    parse::Event,
+    control::{BackendRequest, BackendRequestMap},
+    metrics::Metrics,
+    outbound::{Outbound, OutboundMap, OutboundSender},
+    parse::{Clamped, Event, Init},
+    profile::{Config, Profile, profile_name_of, profile_of},
+    trace::TraceRecorder,
 };
 
-fn process_buf(
+// Process the parsed events of one message
+fn process_message(
     userdata: &Arc<RwMap>,
+    identity: &DeviceIdentity,
+    profile: &Profile,
+    outbound: &OutboundSender,
     lazy_backend: &mut Option<InputBackend>,
-    buf: &mut ByteReader,
+    metrics: &Metrics,
+    events: Vec<Result<(Event, Clamped), String>>,
 ) {
-    let event = match Event::parse(buf) {
-        Ok(event) => event,
-        Err(err) => {
-            tracing::error!("Failed to parse event: {}", err);
-            return;
+    Metrics::count(&metrics.messages);
+    for event in events {
+        match event {
+            Ok((event, clamped)) => {
+                Metrics::count(&metrics.events);
+                if clamped {
+                    Metrics::count(&metrics.clamped);
+                }
+                process_event(userdata, identity, profile, outbound, lazy_backend, event)
+            }
+            Err(err) => {
+                Metrics::count(&metrics.rejected);
+                tracing::error!("Failed to parse event: {}", err);
+            }
         }
-    };
+    }
+}
 
+fn process_event(
+    userdata: &Arc<RwMap>,
+    identity: &DeviceIdentity,
+    profile: &Profile,
+    outbound: &OutboundSender,
+    lazy_backend: &mut Option<InputBackend>,
+    event: Event,
+) {
     // Init backend
     if let Event::Init(ref init) = event {
-        match InputBackend::new(userdata.get_of::<BackendConfig>().unwrap().clone(), init) {
+        reset_backend(lazy_backend);
+
+        // Reuse the kept backend while the geometry is unchanged
+        let kept = lazy_backend.take().or_else(|| {
+            userdata
+                .get_of_mut::<BackendPool>()
+                .unwrap()
+                .take(&identity.serial)
+        });
+        if let Some(backend) = kept {
+            if backend.matches(init) {
+                tracing::info!("Reusing input backend of {}", identity.serial);
+                let backend = lazy_backend.insert(backend);
+                backend.set_outbound(Some(outbound.clone()));
+                return;
+            }
+            tracing::info!(
+                "Geometry of {} changed, recreating input backend",
+                identity.serial
+            );
+        }
+
+        let config = userdata.get_of::<BackendConfig>().unwrap().clone();
+        let profile_config = userdata.get_of::<Config>().unwrap();
+        let mut registry = userdata.get_of_mut::<MergeRegistry>().unwrap();
+        match InputBackend::new(
+            config,
+            identity,
+            profile,
+            &profile_config,
+            &mut registry,
+            init,
+        ) {
             Ok(backend) => {
-                *lazy_backend = Some(backend);
+                let backend = lazy_backend.insert(backend);
+                backend.set_outbound(Some(outbound.clone()));
             }
             Err(err) => {
                 tracing::error!("Failed to initialize input backend: {}", err);
@@ -56,6 +119,14 @@ fn process_buf(
     }
 }
 
+fn reset_backend(lazy_backend: &mut Option<InputBackend>) {
+    if let Some(backend) = lazy_backend
+        && let Err(err) = backend.reset()
+    {
+        tracing::error!("Failed to reset input backend: {}", err);
+    }
+}
+
 pub fn execute_command(command: &str, device: &str) {
     // Spawn a command asynchronously
     let mut child = match TokioCommand::new("sh")
@@ -107,12 +178,26 @@ pub async fn connect_ws(userdata: Arc<RwMap>, port: i32, device: DeviceShort) {
         .unwrap()
         .insert(device.identifier.clone(), started_at);
 
+    let profile_name = profile_name_of(&userdata, &device.identifier);
+    let identity = DeviceIdentity::new(&device.identifier, profile_name.as_deref());
+    let profile = profile_of(&userdata, &device.identifier);
+    let metrics = userdata.get_of::<Arc<Metrics>>().unwrap().clone();
+
+    let mut backoff = Backoff::new(*userdata.get_of::<BackoffConfig>().unwrap());
+
     let uri = Uri::from_str(format!("ws://127.0.0.1:{}", port).as_str()).unwrap();
     loop {
         if let Ok((mut client, _)) = ClientBuilder::from_uri(uri.clone()).connect().await {
             let mut lazy_backend: Option<InputBackend> = None;
+            let mut last_init: Option<Init> = None;
             let command = userdata.get_of::<Command>().unwrap();
             tracing::info!("Connected to ws://127.0.0.1:{}", port);
+            backoff.reset();
+            userdata
+                .get_of_mut::<Status>()
+                .unwrap()
+                .devices
+                .insert(device.identifier.clone(), LinkStatus::Connected);
 
             // Show notification
             if command.notify_connected {
@@ -136,23 +221,105 @@ pub async fn connect_ws(userdata: Arc<RwMap>, port: i32, device: DeviceShort) {
                 execute_command(command, &device.identifier);
             }
 
+            // Record the session for replaying it later
+            let mut recorder = command.record_traces.as_ref().and_then(|dir| {
+                TraceRecorder::create(dir, &device.identifier)
+                    .inspect_err(|err| tracing::error!("Failed to create trace file: {}", err))
+                    .ok()
+            });
+
+            // Messages to the tablet
+            let (outbound, mut outbound_receiver) = unbounded_channel::<Outbound>();
+            userdata
+                .get_mut::<OutboundMap>("outbound_map")
+                .unwrap()
+                .insert(device.identifier.clone(), outbound.clone());
+
+            // Requests from the control socket
+            let (request_sender, mut request_receiver) = unbounded_channel::<BackendRequest>();
+            userdata
+                .get_mut::<BackendRequestMap>("backend_request_map")
+                .unwrap()
+                .insert(device.identifier.clone(), request_sender);
+
+            // Long presses fire while no finger event arrives
+            let mut gesture_ticker = interval(Duration::from_millis(50));
+            gesture_ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
             // Get messages from server
-            while let Some(item) = client.next().await {
-                let msg = match item {
-                    Ok(msg) => msg,
-                    Err(err) => {
-                        tracing::error!("Failed to read message: {}", err);
-                        continue;
-                    }
-                };
+            loop {
+                tokio::select! {
+                    item = client.next() => {
+                        let Some(item) = item else {
+                            break;
+                        };
+                        let msg = match item {
+                            Ok(msg) => msg,
+                            Err(err) => {
+                                tracing::error!("Failed to read message: {}", err);
+                                reset_backend(&mut lazy_backend);
+                                continue;
+                            }
+                        };
 
-                if !msg.is_binary() {
-                    continue;
-                }
+                        if !msg.is_binary() {
+                            continue;
+                        }
+
+                        if let Some(ref mut recorder) = recorder
+                            && let Err(err) = recorder.record(msg.as_payload())
+                        {
+                            tracing::error!("Failed to record trace: {}", err);
+                        }
 
-                let mut buf = ByteReader::from_bytes(msg.as_payload());
-                buf.set_endian(Endian::LittleEndian);
-                process_buf(&userdata, &mut lazy_backend, &mut buf);
+                        let mut buf = ByteReader::from_bytes(msg.as_payload());
+                        buf.set_endian(Endian::LittleEndian);
+                        process_message(
+                            &userdata,
+                            &identity,
+                            &profile,
+                            &outbound,
+                            &mut lazy_backend,
+                            &metrics,
+                            Event::parse_message(&mut buf, &mut last_init),
+                        );
+                    }
+                    Some(message) = outbound_receiver.recv() => {
+                        if let Err(err) = client.send(Message::binary(message.encode())).await {
+                            tracing::error!("Failed to send message: {}", err);
+                        }
+                    }
+                    Some(request) = request_receiver.recv() => {
+                        let result = match lazy_backend {
+                            Some(ref mut backend) => (request.task)(backend),
+                            None => Err(String::from("Input backend is not initialized")),
+                        };
+                        let _ = request.reply.send(result);
+                    }
+                    _ = gesture_ticker.tick() => {
+                        if let Some(ref mut backend) = lazy_backend
+                            && let Err(err) = backend.tick()
+                        {
+                            tracing::error!("Failed to process gestures: {}", err);
+                        }
+                    }
+                }
+            }
+            userdata
+                .get_mut::<OutboundMap>("outbound_map")
+                .unwrap()
+                .remove(&device.identifier);
+            userdata
+                .get_mut::<BackendRequestMap>("backend_request_map")
+                .unwrap()
+                .remove(&device.identifier);
+            reset_backend(&mut lazy_backend);
+            if let Some(mut backend) = lazy_backend.take() {
+                backend.set_outbound(None);
+                userdata
+                    .get_of_mut::<BackendPool>()
+                    .unwrap()
+                    .release(device.identifier.clone(), backend);
             }
 
             tracing::info!("Disconnected from ws://127.0.0.1:{}", port);
@@ -180,21 +347,34 @@ pub async fn connect_ws(userdata: Arc<RwMap>, port: i32, device: DeviceShort) {
             }
         }
 
-        match userdata
-            .get::<WorkerIdMap>("worker_id_map")
-            .unwrap()
-            .get(&device.identifier)
-        {
-            None => {
-                break;
-            }
-            Some(id) => {
-                if id != &started_at {
+        let delay = {
+            // The status is written under the worker id lock, so a disconnect cannot miss it
+            let worker_ids = userdata.get::<WorkerIdMap>("worker_id_map").unwrap();
+            match worker_ids.get(&device.identifier) {
+                None => {
                     break;
                 }
+                Some(id) => {
+                    if id != &started_at {
+                        break;
+                    }
+                }
             }
-        }
 
-        sleep(Duration::from_secs(3)).await;
+            let attempt = backoff.attempt() + 1;
+            let delay = backoff.next_delay();
+            tracing::debug!(
+                "Retrying ws://127.0.0.1:{} in {:.1}s (attempt {})",
+                port,
+                delay.as_secs_f32(),
+                attempt
+            );
+            userdata.get_of_mut::<Status>().unwrap().devices.insert(
+                device.identifier.clone(),
+                LinkStatus::retrying(delay, attempt),
+            );
+            delay
+        };
+        sleep(delay).await;
     }
 }
@@ -0,0 +1,191 @@
+use std::{collections::HashMap, os::unix::fs::FileTypeExt, path::PathBuf, sync::Arc};
+
+use qwreey_utility_rs::{ErrToString, RwMap};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+    sync::{mpsc::UnboundedSender, oneshot},
+    task::JoinHandle,
+};
+
+use crate::{
+    backend::InputBackend,
+    backoff::Status,
+    metrics::Metrics,
+    outbound::{Outbound, send_to},
+    profile::{Mode, profile_of},
+};
+
+// 외부 스크립트에서 실행중인 데몬을 제어하기 위한 unix socket.
+// 한 줄에 하나의 명령을 받고 "ok ..." 또는 "error: ..." 로 응답합니다.
+//
+//   haptic <serial> [duration] [amplitude]
+//   mode <serial> [pen|touchpad|touchscreen|pen+touchpad|pen+touchscreen|next]
+//   preset <serial> [name|next]
+//   precision <serial> [on|off|toggle]
+//   metrics
+//   status     adb / device connections, "retrying:<seconds to the next retry>:<attempt>"
+
+// Work run by the connection task on its input backend
+pub type BackendTask = Box<dyn FnOnce(&mut InputBackend) -> Result<String, String> + Send>;
+
+pub struct BackendRequest {
+    pub task: BackendTask,
+    pub reply: oneshot::Sender<Result<String, String>>,
+}
+
+pub type BackendRequestSender = UnboundedSender<BackendRequest>;
+pub type BackendRequestMap = HashMap<String, BackendRequestSender>;
+
+// Run the task on the backend of the connected device and wait for the result
+pub async fn with_backend(
+    userdata: &Arc<RwMap>,
+    serial: &str,
+    task: impl FnOnce(&mut InputBackend) -> Result<String, String> + Send + 'static,
+) -> Result<String, String> {
+    let not_connected = || format!("{} is not connected", serial);
+    let sender = userdata
+        .get::<BackendRequestMap>("backend_request_map")
+        .unwrap()
+        .get(serial)
+        .cloned()
+        .ok_or_else(not_connected)?;
+    let (reply, receiver) = oneshot::channel();
+    sender
+        .send(BackendRequest {
+            task: Box::new(task),
+            reply,
+        })
+        .map_err(|_| not_connected())?;
+    receiver.await.map_err(|_| not_connected())?
+}
+
+fn parse_arg<T: std::str::FromStr>(arg: Option<&str>, default: T) -> Result<T, String>
+where
+    T::Err: ToString,
+{
+    match arg {
+        Some(arg) => arg.parse::<T>().err_to_string(),
+        None => Ok(default),
+    }
+}
+
+async fn handle_command(userdata: &Arc<RwMap>, line: &str) -> Result<String, String> {
+    let mut args = line.split_whitespace();
+    let Some(command) = args.next() else {
+        return Err(String::from("Empty command"));
+    };
+
+    match command {
+        "haptic" => {
+            let serial = args
+                .next()
+                .ok_or("Usage: haptic <serial> [duration] [amplitude]")?;
+            let haptic = profile_of(userdata, serial).haptic;
+            let message = Outbound::Haptic {
+                duration: parse_arg(args.next(), haptic.duration)?,
+                amplitude: parse_arg(args.next(), haptic.amplitude)?,
+            };
+            if !send_to(userdata, serial, message) {
+                return Err(format!("{} is not connected", serial));
+            }
+            Ok(String::new())
+        }
+        "mode" => {
+            let serial = args.next().ok_or("Usage: mode <serial> [mode|next]")?;
+            let mode = match args.next() {
+                None => None,
+                Some("next") => Some(None),
+                Some(mode) => Some(Some(mode.parse::<Mode>()?)),
+            };
+            with_backend(userdata, serial, move |backend| {
+                match mode {
+                    Some(Some(mode)) => backend.set_mode(mode)?,
+                    Some(None) => backend.cycle_mode()?,
+                    None => {}
+                }
+                Ok(backend.mode().to_string())
+            })
+            .await
+        }
+        "preset" => {
+            let serial = args.next().ok_or("Usage: preset <serial> [name|next]")?;
+            let preset = args.next().map(str::to_string);
+            with_backend(userdata, serial, move |backend| {
+                match preset.as_deref() {
+                    Some("next") => backend.cycle_preset(),
+                    Some(name) => backend.set_preset(name)?,
+                    None => {}
+                }
+                Ok(backend.preset().unwrap_or("none").to_string())
+            })
+            .await
+        }
+        "precision" => {
+            let serial = args
+                .next()
+                .ok_or("Usage: precision <serial> [on|off|toggle]")?;
+            let precision = match args.next() {
+                None => None,
+                Some("on") => Some(Some(true)),
+                Some("off") => Some(Some(false)),
+                Some("toggle") => Some(None),
+                Some(arg) => return Err(format!("Unknown precision argument {}", arg)),
+            };
+            with_backend(userdata, serial, move |backend| {
+                match precision {
+                    Some(Some(precision)) => backend.set_precision(precision),
+                    Some(None) => backend.set_precision(!backend.precision()),
+                    None => {}
+                }
+                Ok(String::from(if backend.precision() { "on" } else { "off" }))
+            })
+            .await
+        }
+        "metrics" => Ok(userdata.get_of::<Arc<Metrics>>().unwrap().summary()),
+        "status" => Ok(userdata.get_of::<Status>().unwrap().summary()),
+        _ => Err(format!("Unknown command {}", command)),
+    }
+}
+
+async fn handle_client(userdata: Arc<RwMap>, stream: UnixStream) {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    while let Ok(Some(line)) = lines.next_line().await {
+        let response = match handle_command(&userdata, line.trim()).await {
+            Ok(output) if output.is_empty() => String::from("ok\n"),
+            Ok(output) => format!("ok {}\n", output),
+            Err(err) => format!("error: {}\n", err),
+        };
+        if let Err(err) = writer.write_all(response.as_bytes()).await {
+            tracing::error!("Failed to write control socket response: {}", err);
+            break;
+        }
+    }
+}
+
+pub fn run_control_socket(userdata: Arc<RwMap>, path: PathBuf) -> Result<JoinHandle<()>, String> {
+    // Remove the socket left by the last run, never anything else at the path
+    if let Ok(metadata) = std::fs::symlink_metadata(&path) {
+        if !metadata.file_type().is_socket() {
+            return Err(format!("{} exists and is not a socket", path.display()));
+        }
+        std::fs::remove_file(&path).err_to_string()?;
+    }
+    let listener = UnixListener::bind(&path).err_to_string()?;
+    tracing::info!("Control socket listening on {}", path.display());
+
+    Ok(tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    tokio::spawn(handle_client(userdata.clone(), stream));
+                }
+                Err(err) => {
+                    tracing::error!("Failed to accept control socket client: {}", err);
+                }
+            }
+        }
+    }))
+}
@@ -0,0 +1,69 @@
+use std::sync::Arc;
+
+use qwreey_utility_rs::{ErrToString, RwMap};
+use zbus::{Connection, connection, fdo, interface};
+
+use crate::{control::with_backend, profile::Mode};
+
+// session bus 에서 실행중인 데몬을 제어하는 D-Bus 인터페이스.
+// control socket 의 mode 명령과 같은 일을 하며, 각 method 는 바뀐 뒤의 mode 를 돌려줍니다.
+//
+//   busctl --user call io.github.pendroid.Linux /io/github/pendroid/Linux \
+//       io.github.pendroid.Linux SetMode ss <serial> touchpad
+//
+//   Mode(serial)              current mode
+//   SetMode(serial, mode)     pen|touchpad|touchscreen|pen+touchpad|pen+touchscreen
+//   CycleMode(serial)         next mode of the profile
+
+const NAME: &str = "io.github.pendroid.Linux";
+const PATH: &str = "/io/github/pendroid/Linux";
+
+struct Daemon {
+    userdata: Arc<RwMap>,
+}
+
+impl Daemon {
+    async fn update_mode(&self, serial: String, mode: Option<Option<Mode>>) -> fdo::Result<String> {
+        with_backend(&self.userdata, &serial, move |backend| {
+            match mode {
+                Some(Some(mode)) => backend.set_mode(mode)?,
+                Some(None) => backend.cycle_mode()?,
+                None => {}
+            }
+            Ok(backend.mode().to_string())
+        })
+        .await
+        .map_err(fdo::Error::Failed)
+    }
+}
+
+#[interface(name = "io.github.pendroid.Linux")]
+impl Daemon {
+    async fn mode(&self, serial: String) -> fdo::Result<String> {
+        self.update_mode(serial, None).await
+    }
+
+    async fn set_mode(&self, serial: String, mode: String) -> fdo::Result<String> {
+        let mode = mode.parse::<Mode>().map_err(fdo::Error::InvalidArgs)?;
+        self.update_mode(serial, Some(Some(mode))).await
+    }
+
+    async fn cycle_mode(&self, serial: String) -> fdo::Result<String> {
+        self.update_mode(serial, Some(None)).await
+    }
+}
+
+// Serve the interface until the returned connection is dropped
+pub async fn run_dbus(userdata: Arc<RwMap>) -> Result<Connection, String> {
+    let connection = connection::Builder::session()
+        .err_to_string()?
+        .name(NAME)
+        .err_to_string()?
+        .serve_at(PATH, Daemon { userdata })
+        .err_to_string()?
+        .build()
+        .await
+        .err_to_string()?;
+    tracing::info!("D-Bus interface available as {} on the session bus", NAME);
+    Ok(connection)
+}
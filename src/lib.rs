@@ -0,0 +1,24 @@
+use std::collections::HashMap;
+
+use tokio::{task::JoinHandle, time::Instant};
+
+pub mod adb_client;
+pub mod adb_tracker;
+pub mod backend;
+pub mod backoff;
+pub mod cli;
+mod connect_ws;
+pub mod control;
+pub mod dbus;
+pub mod metrics;
+pub mod outbound;
+pub mod parse;
+pub mod profile;
+pub mod setup_autolaunch;
+pub mod setup_daemonize;
+pub mod setup_logging;
+pub mod simulate;
+pub mod trace;
+
+pub type DeviceMap = HashMap<String, JoinHandle<()>>;
+pub type WorkerIdMap = HashMap<String, Instant>;
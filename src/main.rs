@@ -1,24 +1,22 @@
-use std::{collections::HashMap, sync::Arc};
-
-mod adb_tracker;
-mod backend;
-mod cli;
-mod connect_ws;
-mod parse;
-mod setup_autolaunch;
-mod setup_daemonize;
-mod setup_logging;
+use std::sync::Arc;
 
 use clap::Parser;
-use cli::Command;
-
 use qwreey_utility_rs::{ErrToString, RwMap};
-use tokio::{task::JoinHandle, time::Instant};
 
-use crate::backend::BackendConfig;
-
-pub type DeviceMap = HashMap<String, JoinHandle<()>>;
-pub type WorkerIdMap = HashMap<String, Instant>;
+use pendroid_linux::{
+    DeviceMap, WorkerIdMap,
+    adb_client::{AdbClient, AdbServerClient},
+    adb_tracker,
+    backend::{self, BackendConfig, BackendPool, MergeRegistry},
+    backoff::{BackoffConfig, Status},
+    cli::{Command, SimulateArgs, Subcommand},
+    control::{self, BackendRequestMap},
+    dbus,
+    metrics::Metrics,
+    outbound::OutboundMap,
+    profile::Config,
+    setup_autolaunch, setup_daemonize, setup_logging, simulate,
+};
 
 #[tokio::main]
 async fn run(command: Command) -> Result<(), String> {
@@ -26,25 +24,73 @@ async fn run(command: Command) -> Result<(), String> {
     userdata.insert_of(command.clone());
 
     userdata.insert_of(BackendConfig {
+        evdev_vendor_id: command.evdev_vendor_id,
+        evdev_stylus_product_id: command.evdev_stylus_product_id,
+        evdev_trackpad_product_id: command.evdev_trackpad_product_id,
+        evdev_keyboard_product_id: command.evdev_keyboard_product_id,
+        evdev_touchscreen_product_id: command.evdev_touchscreen_product_id,
         evdev_trackpad_flat: command.evdev_trackpad_flat,
         evdev_trackpad_res: command.evdev_trackpad_res,
         evdev_trackpad_fuzz: command.evdev_trackpad_fuzz,
     });
+    let config = match command.config {
+        Some(ref path) => Config::load(path)?,
+        None => Config::default(),
+    };
+    config.validate(&command.devices)?;
+    userdata.insert_of(config);
+    userdata.insert_of(MergeRegistry::default());
+    userdata.insert_of(Arc::new(Metrics::default()));
+    userdata.insert_of(BackoffConfig::new(
+        command.retry_initial,
+        command.retry_max,
+        command.retry_jitter,
+    )?);
+    userdata.insert_of(Status::default());
+
     userdata.insert("worker_id_map", WorkerIdMap::new());
     userdata.insert_of(command.devices);
     userdata.insert("device_map", DeviceMap::new());
+    userdata.insert("outbound_map", OutboundMap::new());
+    userdata.insert("backend_request_map", BackendRequestMap::new());
+    userdata.insert_of(BackendPool::default());
+    userdata.insert_of::<Arc<dyn AdbClient>>(Arc::new(AdbServerClient::new(command.adb_server)));
 
-    adb_tracker::run_adb_tracker(userdata)
-        .await
-        .err_to_string()?;
+    backend::run_backend_sweeper(userdata.clone());
+    if let Some(ref path) = command.control_socket {
+        control::run_control_socket(userdata.clone(), path.clone())?;
+    }
+    // Kept until the daemon exits
+    let _dbus = if command.dbus {
+        Some(dbus::run_dbus(userdata.clone()).await?)
+    } else {
+        None
+    };
+
+    if command.no_adb {
+        adb_tracker::connect_direct(&userdata);
+        std::future::pending::<()>().await;
+    } else {
+        adb_tracker::run_adb_tracker(userdata)
+            .await
+            .err_to_string()?;
+    }
 
     Ok(())
 }
 
+#[tokio::main]
+async fn run_simulate(args: SimulateArgs) -> Result<(), String> {
+    simulate::serve(args).await
+}
+
 fn main() -> Result<(), String> {
     let command = Command::parse();
 
     setup_logging::config(command.verbose);
+    if let Some(Subcommand::Simulate(args)) = command.subcommand {
+        return run_simulate(args);
+    }
     setup_autolaunch::config(command.enable_autolaunch, command.disable_autolaunch)?;
     setup_daemonize::config(command.daemon);
 
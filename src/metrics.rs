@@ -0,0 +1,30 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// 파싱 결과 카운터, control socket 의 metrics 명령으로 확인할 수 있습니다
+
+#[derive(Default)]
+pub struct Metrics {
+    pub messages: AtomicU64,
+    // Parsed and accepted
+    pub events: AtomicU64,
+    // Dropped as malformed
+    pub rejected: AtomicU64,
+    // Kept with values clamped into range
+    pub clamped: AtomicU64,
+}
+
+impl Metrics {
+    pub fn count(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn summary(&self) -> String {
+        format!(
+            "messages={} events={} rejected={} clamped={}",
+            self.messages.load(Ordering::Relaxed),
+            self.events.load(Ordering::Relaxed),
+            self.rejected.load(Ordering::Relaxed),
+            self.clamped.load(Ordering::Relaxed),
+        )
+    }
+}
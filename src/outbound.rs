@@ -0,0 +1,50 @@
+use std::{collections::HashMap, sync::Arc};
+
+use bytebuffer::{ByteBuffer, Endian};
+use qwreey_utility_rs::RwMap;
+use tokio::sync::mpsc::UnboundedSender;
+
+// 호스트에서 태블릿으로 보내는 메시지
+
+pub enum Outbound {
+    // Vibrate for duration (ms) with amplitude (1 ~ 255)
+    Haptic { duration: u16, amplitude: u8 },
+    // Mapping preset selected on the host
+    Preset { name: String },
+}
+
+impl Outbound {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = ByteBuffer::new();
+        buf.set_endian(Endian::LittleEndian);
+        match self {
+            Outbound::Haptic {
+                duration,
+                amplitude,
+            } => {
+                buf.write_u8(0x80);
+                buf.write_u16(*duration);
+                buf.write_u8(*amplitude);
+            }
+            Outbound::Preset { name } => {
+                buf.write_u8(0x81);
+                // Profile validation keeps preset names within u16
+                buf.write_u16(name.len() as u16);
+                buf.write_bytes(name.as_bytes());
+            }
+        }
+        buf.into_vec()
+    }
+}
+
+pub type OutboundSender = UnboundedSender<Outbound>;
+pub type OutboundMap = HashMap<String, OutboundSender>;
+
+// Send to the connected device, false when it is not connected
+pub fn send_to(userdata: &Arc<RwMap>, serial: &str, message: Outbound) -> bool {
+    userdata
+        .get::<OutboundMap>("outbound_map")
+        .unwrap()
+        .get(serial)
+        .is_some_and(|sender| sender.send(message).is_ok())
+}
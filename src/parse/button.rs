@@ -0,0 +1,18 @@
+use bytebuffer::ByteReader;
+use qwreey_utility_rs::ErrToString;
+
+// Virtual button of the on-screen pad or a hardware key of the tablet
+#[allow(unused)]
+pub struct Button {
+    pub id: u8,
+    pub pressed: bool,
+}
+
+impl Button {
+    pub fn new(buf: &mut ByteReader) -> Result<Self, String> {
+        Ok(Button {
+            id: buf.read_u8().err_to_string()?,
+            pressed: buf.read_u8().err_to_string()? != 0,
+        })
+    }
+}
@@ -11,6 +11,24 @@ pub struct Finger {
     pub y: i16,
 }
 
+// Every slot changed at once, reported as one evdev frame
+pub struct FingerFrame {
+    // Milliseconds, same clock as the stylus timestamp
+    pub timestamp: i32,
+    pub fingers: Vec<Finger>,
+}
+
+impl FingerFrame {
+    pub fn new(buf: &mut ByteReader) -> Result<Self, String> {
+        let timestamp = buf.read_i32().err_to_string()?;
+        let count = buf.read_u8().err_to_string()?;
+        let fingers = (0..count)
+            .map(|_| Finger::new(buf))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(FingerFrame { timestamp, fingers })
+    }
+}
+
 impl Finger {
     pub fn new(buf: &mut ByteReader) -> Result<Self, String> {
         Ok(Finger {
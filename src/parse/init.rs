@@ -1,17 +1,82 @@
 use bytebuffer::ByteReader;
 use qwreey_utility_rs::ErrToString;
+use serde::Deserialize;
+
+// Unit of the stylus tilt_x / tilt_y fields, both in centidegrees
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum TiltFormat {
+    // Tilt along X / Y, -9000 ~ 9000
+    Xy,
+    // tilt_x is the Android orientation (-18000 ~ 18000, 0 points up, clockwise),
+    // tilt_y is the tilt from the screen normal (0 ~ 9000)
+    Polar,
+}
 
 #[allow(unused)]
+#[derive(Clone, PartialEq)]
 pub struct Init {
     pub width: u16,
     pub height: u16,
+    pub tilt_format: TiltFormat,
+    // Highest pressure value the pen reports
+    pub pressure_max: u16,
+    // Physical size of the surface, 0 when unknown
+    pub width_mm: u16,
+    pub height_mm: u16,
+    // Centidegrees
+    pub tilt_max: i16,
+}
+
+fn has_remaining(buf: &ByteReader) -> bool {
+    buf.get_rpos() < buf.len()
 }
 
 impl Init {
+    // Units per mm of the X / Y axes, falls back to 1 when the size is unknown
+    pub fn resolution(&self) -> (i32, i32) {
+        let axis = |units: u16, mm: u16| match mm {
+            0 => 1,
+            mm => ((units as f32 / mm as f32).round() as i32).max(1),
+        };
+        (
+            axis(self.width, self.width_mm),
+            axis(self.height, self.height_mm),
+        )
+    }
+
     pub fn new(buf: &mut ByteReader) -> Result<Self, String> {
-        Ok(Init {
-            width: buf.read_u16().err_to_string()?,
-            height: buf.read_u16().err_to_string()?,
-        })
+        let width = buf.read_u16().err_to_string()?;
+        let height = buf.read_u16().err_to_string()?;
+        // Older apps end the message after the size
+        let tilt_format = if has_remaining(buf) {
+            match buf.read_u8().err_to_string()? {
+                0x0 => TiltFormat::Xy,
+                0x1 => TiltFormat::Polar,
+                _ => return Err(String::from("Got unexpected tilt format")),
+            }
+        } else {
+            TiltFormat::Xy
+        };
+        let mut init = Init {
+            width,
+            height,
+            tilt_format,
+            pressure_max: 4096,
+            width_mm: 0,
+            height_mm: 0,
+            tilt_max: 9000,
+        };
+        // Device capabilities, sent after the tilt format
+        if has_remaining(buf) {
+            init.pressure_max = buf.read_u16().err_to_string()?;
+            init.width_mm = buf.read_u16().err_to_string()?;
+            init.height_mm = buf.read_u16().err_to_string()?;
+            init.tilt_max = buf.read_i16().err_to_string()?;
+            if init.pressure_max == 0 || !(0..=9000).contains(&init.tilt_max) {
+                return Err(String::from("Got invalid device capabilities"));
+            }
+        }
+        Ok(init)
     }
 }
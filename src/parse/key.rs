@@ -0,0 +1,30 @@
+use bytebuffer::ByteReader;
+use qwreey_utility_rs::ErrToString;
+
+// Input of the Android soft keyboard
+#[allow(unused)]
+pub enum Key {
+    // Android KeyEvent keycode
+    Code { code: u16, pressed: bool },
+    // Committed text without keycodes
+    Text(String),
+}
+
+impl Key {
+    pub fn new(buf: &mut ByteReader) -> Result<Self, String> {
+        let kind = buf.read_u8().err_to_string()?;
+        Ok(match kind {
+            0x0 => Key::Code {
+                code: buf.read_u16().err_to_string()?,
+                pressed: buf.read_u8().err_to_string()? != 0,
+            },
+            0x1 => {
+                let length = buf.read_u16().err_to_string()? as usize;
+                Key::Text(
+                    String::from_utf8(buf.read_bytes(length).err_to_string()?).err_to_string()?,
+                )
+            }
+            _ => return Err(String::from("Got unexpected key event kind")),
+        })
+    }
+}
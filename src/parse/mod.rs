@@ -1,21 +1,90 @@
-use bytebuffer::ByteReader;
+use bytebuffer::{ByteReader, Endian};
 use qwreey_utility_rs::ErrToString;
 
+mod button;
 mod finger;
 mod init;
+mod key;
 mod stylus;
+mod validate;
 
-pub use finger::Finger;
-pub use init::Init;
+pub use button::Button;
+pub use finger::{Finger, FingerFrame};
+pub use init::{Init, TiltFormat};
+pub use key::Key;
 pub use stylus::Stylus;
+pub use validate::{Clamped, MAX_SLOTS};
 
 pub enum Event {
     Init(Init),
     Stylus(Stylus),
     Finger(Finger),
+    FingerFrame(FingerFrame),
+    Button(Button),
+    Key(Key),
 }
 
+// Event type of a message carrying many events, each after its u16 length
+const BATCH: u8 = 0x6;
+
 impl Event {
+    // Every event of a websocket message, in order.
+    // init holds the last Init of the connection and is replaced by the parsed ones.
+    pub fn parse_message(
+        buf: &mut ByteReader,
+        init: &mut Option<Init>,
+    ) -> Vec<Result<(Event, Clamped), String>> {
+        if buf.as_bytes().get(buf.get_rpos()) != Some(&BATCH) {
+            return vec![Event::parse_strict(buf, init)];
+        }
+
+        let mut events = Vec::new();
+        if let Err(err) = Self::parse_batch(buf, init, &mut events) {
+            events.push(Err(err));
+        }
+        events
+    }
+
+    fn parse_batch(
+        buf: &mut ByteReader,
+        init: &mut Option<Init>,
+        events: &mut Vec<Result<(Event, Clamped), String>>,
+    ) -> Result<(), String> {
+        buf.read_u8().err_to_string()?;
+        let count = buf.read_u16().err_to_string()?;
+        for _ in 0..count {
+            let length = buf.read_u16().err_to_string()? as usize;
+            let bytes = buf.read_bytes(length).err_to_string()?;
+            // Each event reads its own bytes, so optional tails stay inside it
+            let mut event_buf = ByteReader::from_bytes(&bytes);
+            event_buf.set_endian(Endian::LittleEndian);
+            events.push(Event::parse_strict(&mut event_buf, init));
+        }
+        if buf.get_rpos() < buf.len() {
+            return Err(String::from("Got trailing bytes after the batch"));
+        }
+        Ok(())
+    }
+
+    // Parse one event filling the whole buffer, then validate it
+    fn parse_strict(
+        buf: &mut ByteReader,
+        init: &mut Option<Init>,
+    ) -> Result<(Event, Clamped), String> {
+        let mut event = Event::parse(buf)?;
+        if buf.get_rpos() < buf.len() {
+            return Err(format!(
+                "Got {} trailing bytes after the event",
+                buf.len() - buf.get_rpos()
+            ));
+        }
+        let clamped = event.validate(init.as_ref())?;
+        if let Event::Init(ref new_init) = event {
+            *init = Some(new_init.clone());
+        }
+        Ok((event, clamped))
+    }
+
     pub fn parse(buf: &mut ByteReader) -> Result<Event, String> {
         let event_type = buf.read_u8().err_to_string()?;
 
@@ -23,7 +92,95 @@ impl Event {
             0x0 => Event::Init(Init::new(buf)?),
             0x1 => Event::Stylus(Stylus::new(buf)?),
             0x2 => Event::Finger(Finger::new(buf)?),
+            0x3 => Event::Button(Button::new(buf)?),
+            0x4 => Event::Key(Key::new(buf)?),
+            0x5 => Event::FingerFrame(FingerFrame::new(buf)?),
             _ => return Err(String::from("Got unexpected event type")),
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bytebuffer::ByteBuffer;
+
+    use super::*;
+
+    // Hovering stylus event at x
+    fn stylus(x: i16) -> Vec<u8> {
+        let mut buf = ByteBuffer::new();
+        buf.set_endian(Endian::LittleEndian);
+        buf.write_u8(0x1);
+        buf.write_u8(0b100);
+        for value in [0, 0, 0, x, 10] {
+            buf.write_i16(value);
+        }
+        buf.write_i32(0);
+        buf.into_vec()
+    }
+
+    fn batch(count: u16, events: &[Vec<u8>], tail: &[u8]) -> Vec<u8> {
+        let mut buf = ByteBuffer::new();
+        buf.set_endian(Endian::LittleEndian);
+        buf.write_u8(BATCH);
+        buf.write_u16(count);
+        for event in events {
+            buf.write_u16(event.len() as u16);
+            buf.write_bytes(event);
+        }
+        buf.write_bytes(tail);
+        buf.into_vec()
+    }
+
+    // Stylus x of each parsed event, None for an error
+    fn parse(bytes: &[u8]) -> Vec<Option<i16>> {
+        let mut buf = ByteReader::from_bytes(bytes);
+        buf.set_endian(Endian::LittleEndian);
+        Event::parse_message(&mut buf, &mut None)
+            .into_iter()
+            .map(|event| match event {
+                Ok((Event::Stylus(stylus), _)) => Some(stylus.x),
+                Ok(_) => panic!("not a stylus event"),
+                Err(_) => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn batch_framing() {
+        let bad = vec![0x7f];
+        let cases = [
+            ("single event", stylus(1), vec![Some(1)]),
+            ("empty batch", batch(0, &[], &[]), vec![]),
+            (
+                "events in order",
+                batch(2, &[stylus(1), stylus(2)], &[]),
+                vec![Some(1), Some(2)],
+            ),
+            (
+                "count past the events",
+                batch(3, &[stylus(1), stylus(2)], &[]),
+                vec![Some(1), Some(2), None],
+            ),
+            (
+                "length past the end",
+                batch(1, &[], &[0x40, 0x00, 0x1]),
+                vec![None],
+            ),
+            (
+                "bad event before good ones",
+                batch(3, &[bad, stylus(1), stylus(2)], &[]),
+                vec![None, Some(1), Some(2)],
+            ),
+            (
+                "trailing bytes",
+                batch(1, &[stylus(1)], &[0x0]),
+                vec![Some(1), None],
+            ),
+        ];
+
+        for (name, bytes, expected) in cases {
+            assert_eq!(parse(&bytes), expected, "{name}");
+        }
+    }
+}
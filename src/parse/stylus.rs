@@ -6,12 +6,16 @@ pub struct Stylus {
     pub down: bool,
     pub button: bool,
     pub hover: bool,
+    pub button2: bool,
+    // Android TOOL_TYPE_ERASER (the eraser end of the pen)
+    pub eraser: bool,
     pub pressure: i16,
     pub tilt_x: i16,
     pub tilt_y: i16,
     pub x: i16,
     pub y: i16,
     pub timestamp: i32,
+    pub tool_serial: Option<u32>,
 }
 
 impl Stylus {
@@ -21,12 +25,20 @@ impl Stylus {
             down: flags & 0b0000_0001 != 0,
             button: flags & 0b0000_0010 != 0,
             hover: flags & 0b0000_0100 != 0,
+            button2: flags & 0b0000_1000 != 0,
+            eraser: flags & 0b0001_0000 != 0,
             pressure: buf.read_i16().err_to_string()?,
             tilt_x: buf.read_i16().err_to_string()?,
             tilt_y: buf.read_i16().err_to_string()?,
             x: buf.read_i16().err_to_string()?,
             y: buf.read_i16().err_to_string()?,
             timestamp: buf.read_i32().err_to_string()?,
+            // Appended only when the tool reports an id
+            tool_serial: if flags & 0b0010_0000 != 0 {
+                Some(buf.read_u32().err_to_string()?)
+            } else {
+                None
+            },
         })
     }
 }
@@ -0,0 +1,281 @@
+use super::{Event, Finger, Init, Stylus};
+
+// 적대적인 입력에도 backend 가 panic 하지 않도록 event 를 검사합니다.
+// 고칠 수 있는 값은 clamp 하고, 그렇지 않으면 event 를 버립니다.
+
+// Number of MT slots of the touch device
+pub const MAX_SLOTS: u8 = 12;
+// ABS_MT_TRACKING_ID maximum of the touch device
+const MAX_TRACKING_ID: i32 = 65535;
+// Widest tilt value of both formats, the polar orientation
+const MAX_TILT: i16 = 18000;
+
+// Whether a value was clamped
+pub type Clamped = bool;
+
+fn clamp_min<T: PartialOrd + Copy>(value: &mut T, min: T) -> Clamped {
+    if *value < min {
+        *value = min;
+        return true;
+    }
+    false
+}
+
+fn clamp_range<T: PartialOrd + Copy>(value: &mut T, min: T, max: T) -> Clamped {
+    if *value > max {
+        *value = max;
+        return true;
+    }
+    clamp_min(value, min)
+}
+
+fn validate_init(init: &Init) -> Result<Clamped, String> {
+    // Positions are i16 all the way to the devices
+    let max = i16::MAX as u16;
+    if init.width == 0 || init.height == 0 || init.width > max || init.height > max {
+        return Err(format!("Init size must be within 1 ~ {}", max));
+    }
+    Ok(false)
+}
+
+// Surface size, without an Init only the lower bounds are known
+fn surface(init: Option<&Init>) -> (i16, i16) {
+    match init {
+        Some(init) => (init.width as i16, init.height as i16),
+        None => (i16::MAX, i16::MAX),
+    }
+}
+
+fn validate_stylus(stylus: &mut Stylus, init: Option<&Init>) -> Result<Clamped, String> {
+    let (width, height) = surface(init);
+    let pressure_max = init.map_or(i16::MAX, |init| {
+        init.pressure_max.min(i16::MAX as u16) as i16
+    });
+    let pressure = clamp_range(&mut stylus.pressure, 0, pressure_max);
+    let x = clamp_range(&mut stylus.x, 0, width);
+    let y = clamp_range(&mut stylus.y, 0, height);
+    // The tilt format may be overridden by the profile, tilt::convert bounds the result
+    let tilt_x = clamp_range(&mut stylus.tilt_x, -MAX_TILT, MAX_TILT);
+    let tilt_y = clamp_range(&mut stylus.tilt_y, -MAX_TILT, MAX_TILT);
+    Ok(pressure || x || y || tilt_x || tilt_y)
+}
+
+// -1 means the position did not change
+fn clamp_position(value: &mut i16, max: i16) -> Clamped {
+    *value != -1 && clamp_range(value, 0, max)
+}
+
+fn validate_finger(finger: &mut Finger, init: Option<&Init>) -> Result<Clamped, String> {
+    if finger.slot >= MAX_SLOTS {
+        return Err(format!("Finger slot {} is out of range", finger.slot));
+    }
+    if finger.tracking_id < -1 {
+        return Err(format!("Invalid tracking id {}", finger.tracking_id));
+    }
+    // Folded instead of capped, so the ids of consecutive contacts stay distinct
+    let tracking_id = finger.tracking_id > MAX_TRACKING_ID;
+    if tracking_id {
+        finger.tracking_id %= MAX_TRACKING_ID + 1;
+    }
+    let (width, height) = surface(init);
+    let x = clamp_position(&mut finger.x, width);
+    let y = clamp_position(&mut finger.y, height);
+    Ok(tracking_id || x || y)
+}
+
+impl Event {
+    // Positions are clamped to the last Init of the connection
+    pub fn validate(&mut self, init: Option<&Init>) -> Result<Clamped, String> {
+        match self {
+            Event::Init(init) => validate_init(init),
+            Event::Stylus(stylus) => validate_stylus(stylus, init),
+            Event::Finger(finger) => validate_finger(finger, init),
+            Event::FingerFrame(frame) => {
+                let mut clamped = false;
+                for finger in frame.fingers.iter_mut() {
+                    clamped |= validate_finger(finger, init)?;
+                }
+                Ok(clamped)
+            }
+            Event::Button(_) | Event::Key(_) => Ok(false),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytebuffer::{ByteBuffer, ByteReader, Endian};
+
+    use super::*;
+    use crate::parse::TiltFormat;
+
+    fn message(build: impl FnOnce(&mut ByteBuffer)) -> Vec<u8> {
+        let mut buf = ByteBuffer::new();
+        buf.set_endian(Endian::LittleEndian);
+        build(&mut buf);
+        buf.into_vec()
+    }
+
+    fn stylus(pressure: i16, x: i16, y: i16) -> Vec<u8> {
+        message(|buf| {
+            buf.write_u8(0x1);
+            buf.write_u8(0b101);
+            buf.write_i16(pressure);
+            buf.write_i16(0);
+            buf.write_i16(0);
+            buf.write_i16(x);
+            buf.write_i16(y);
+            buf.write_i32(0);
+        })
+    }
+
+    fn finger(slot: u8, tracking_id: i32, x: i16, y: i16) -> Vec<u8> {
+        message(|buf| {
+            buf.write_u8(0x2);
+            buf.write_u8(slot);
+            buf.write_u8(1);
+            buf.write_u8(1);
+            buf.write_i32(tracking_id);
+            buf.write_i16(x);
+            buf.write_i16(y);
+        })
+    }
+
+    fn init(width: u16, height: u16) -> Init {
+        Init {
+            width,
+            height,
+            tilt_format: TiltFormat::Xy,
+            pressure_max: 1000,
+            width_mm: 0,
+            height_mm: 0,
+            tilt_max: 9000,
+        }
+    }
+
+    fn parse(bytes: &[u8], mut init: Option<Init>) -> Result<(Event, Clamped), String> {
+        let mut buf = ByteReader::from_bytes(bytes);
+        buf.set_endian(Endian::LittleEndian);
+        let mut events = Event::parse_message(&mut buf, &mut init);
+        assert_eq!(events.len(), 1);
+        events.remove(0)
+    }
+
+    fn parse_stylus(bytes: &[u8], init: Option<Init>) -> (i16, i16, i16, Clamped) {
+        match parse(bytes, init) {
+            Ok((Event::Stylus(stylus), clamped)) => (stylus.pressure, stylus.x, stylus.y, clamped),
+            _ => panic!("not a stylus event"),
+        }
+    }
+
+    fn parse_finger(bytes: &[u8], init: Option<Init>) -> (i32, i16, i16, Clamped) {
+        match parse(bytes, init) {
+            Ok((Event::Finger(finger), clamped)) => {
+                (finger.tracking_id, finger.x, finger.y, clamped)
+            }
+            _ => panic!("not a finger event"),
+        }
+    }
+
+    #[test]
+    fn trailing_bytes_are_rejected() {
+        let mut bytes = stylus(100, 10, 10);
+        bytes.push(0);
+        assert!(parse(&bytes, None).is_err());
+    }
+
+    #[test]
+    fn out_of_range_slot_is_rejected() {
+        assert!(parse(&finger(MAX_SLOTS - 1, 1, 10, 10), None).is_ok());
+        assert!(parse(&finger(MAX_SLOTS, 1, 10, 10), None).is_err());
+        assert!(parse(&finger(0, -2, 10, 10), None).is_err());
+    }
+
+    #[test]
+    fn oversized_init_is_rejected() {
+        let init = |width: u16, height: u16| {
+            message(|buf| {
+                buf.write_u8(0x0);
+                buf.write_u16(width);
+                buf.write_u16(height);
+            })
+        };
+        assert!(parse(&init(2000, 1200), None).is_ok());
+        assert!(parse(&init(0, 1200), None).is_err());
+        assert!(parse(&init(i16::MAX as u16 + 1, 1200), None).is_err());
+    }
+
+    #[test]
+    fn stylus_clamping() {
+        let cases = [
+            // (pressure, x, y), Init, expected (pressure, x, y, clamped)
+            ((100, 10, 10), None, (100, 10, 10, false)),
+            ((-5, 10, 10), None, (0, 10, 10, true)),
+            ((100, -3, -3), None, (100, 0, 0, true)),
+            ((5000, 30000, 30000), None, (5000, 30000, 30000, false)),
+            ((5000, 10, 10), Some(init(2000, 1200)), (1000, 10, 10, true)),
+            (
+                (100, 3000, 3000),
+                Some(init(2000, 1200)),
+                (100, 2000, 1200, true),
+            ),
+            (
+                (100, 2000, 1200),
+                Some(init(2000, 1200)),
+                (100, 2000, 1200, false),
+            ),
+        ];
+        for ((pressure, x, y), init, expected) in cases {
+            assert_eq!(
+                parse_stylus(&stylus(pressure, x, y), init),
+                expected,
+                "{pressure} {x} {y}"
+            );
+        }
+    }
+
+    #[test]
+    fn finger_clamping() {
+        let cases = [
+            // (tracking_id, x, y), Init, expected (tracking_id, x, y, clamped)
+            ((1, 10, 10), None, (1, 10, 10, false)),
+            ((1, -1, -1), None, (1, -1, -1, false)),
+            ((1, -7, 10), None, (1, 0, 10, true)),
+            ((1, 30000, 30000), None, (1, 30000, 30000, false)),
+            (
+                (1, 30000, 30000),
+                Some(init(2000, 1200)),
+                (1, 2000, 1200, true),
+            ),
+            ((1, -1, 30000), Some(init(2000, 1200)), (1, -1, 1200, true)),
+            ((65536 + 3, 10, 10), None, (3, 10, 10, true)),
+        ];
+        for ((tracking_id, x, y), init, expected) in cases {
+            assert_eq!(
+                parse_finger(&finger(0, tracking_id, x, y), init),
+                expected,
+                "{tracking_id} {x} {y}"
+            );
+        }
+    }
+
+    #[test]
+    fn init_applies_to_the_next_events() {
+        let mut last_init = None;
+        let init_message = message(|buf| {
+            buf.write_u8(0x0);
+            buf.write_u16(500);
+            buf.write_u16(400);
+        });
+        for bytes in [init_message, stylus(100, 900, 900)] {
+            let mut reader = ByteReader::from_bytes(&bytes);
+            reader.set_endian(Endian::LittleEndian);
+            for event in Event::parse_message(&mut reader, &mut last_init) {
+                if let (Event::Stylus(stylus), clamped) = event.unwrap() {
+                    assert_eq!((stylus.x, stylus.y, clamped), (500, 400, true));
+                }
+            }
+        }
+        assert!(last_init.is_some_and(|init| init.width == 500));
+    }
+}
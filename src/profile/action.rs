@@ -0,0 +1,133 @@
+use std::str::FromStr;
+
+use evdev::KeyCode;
+use serde::Deserialize;
+
+use super::Mode;
+
+// profile 에서 버튼 / 제스처에 할당할 수 있는 동작
+//
+// off, eraser, eraser-toggle, stylus, stylus2, right-click, keys:ctrl+z,
+// scroll:ctrl+up, command:notify-send hello, mode:pen, mode-cycle,
+// preset:monitor-1, preset-cycle, precision
+
+#[derive(Deserialize, Clone, PartialEq, Debug)]
+#[serde(try_from = "String")]
+pub enum Action {
+    Off,
+    // Eraser tool while held
+    Eraser,
+    // Flip between pen and eraser tool
+    EraserToggle,
+    Stylus,
+    Stylus2,
+    RightClick,
+    Keys(Vec<KeyCode>),
+    // Wheel steps, with modifier keys held while scrolling
+    Scroll { keys: Vec<KeyCode>, x: i32, y: i32 },
+    // Shell command, run with sh -c
+    Command(String),
+    Mode(Mode),
+    // Next mode of the profile modes list
+    CycleMode,
+    // Mapping preset by name
+    Preset(String),
+    CyclePreset,
+    // Toggle the precision mode
+    Precision,
+}
+
+impl Action {
+    // Usable without a pen, from pad buttons and gestures
+    pub fn is_standalone(&self) -> bool {
+        !matches!(self, Action::Eraser | Action::Stylus | Action::Stylus2)
+    }
+}
+
+impl FromStr for Action {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Ok(match value {
+            "off" => Action::Off,
+            "eraser" => Action::Eraser,
+            "eraser-toggle" => Action::EraserToggle,
+            "stylus" => Action::Stylus,
+            "stylus2" => Action::Stylus2,
+            "right-click" => Action::RightClick,
+            "mode-cycle" => Action::CycleMode,
+            "preset-cycle" => Action::CyclePreset,
+            "precision" => Action::Precision,
+            _ => match value.split_once(':') {
+                Some(("keys", chord)) => Action::Keys(parse_chord(chord)?),
+                Some(("scroll", scroll)) => parse_scroll(scroll)?,
+                Some(("mode", mode)) => Action::Mode(mode.parse()?),
+                Some(("preset", name)) => Action::Preset(name.to_string()),
+                Some(("command", command)) if !command.is_empty() => {
+                    Action::Command(command.to_string())
+                }
+                _ => return Err(format!("Unknown action {}", value)),
+            },
+        })
+    }
+}
+
+impl TryFrom<String> for Action {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+// Keys pressed together, "off" for none
+#[derive(Deserialize, Clone, PartialEq, Debug)]
+#[serde(try_from = "String")]
+pub struct Chord(pub Vec<KeyCode>);
+
+impl TryFrom<String> for Chord {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        if value == "off" {
+            return Ok(Chord(Vec::new()));
+        }
+        parse_chord(&value).map(Chord)
+    }
+}
+
+// e.g. "ctrl+shift+z", "KEY_F5", "BTN_MIDDLE"
+pub fn parse_chord(chord: &str) -> Result<Vec<KeyCode>, String> {
+    chord
+        .split('+')
+        .map(|key| {
+            let key = key.trim();
+            let name = match key.to_lowercase().as_str() {
+                "ctrl" | "control" => String::from("KEY_LEFTCTRL"),
+                "shift" => String::from("KEY_LEFTSHIFT"),
+                "alt" => String::from("KEY_LEFTALT"),
+                "altgr" => String::from("KEY_RIGHTALT"),
+                "super" | "meta" | "win" => String::from("KEY_LEFTMETA"),
+                _ if key.starts_with("KEY_") || key.starts_with("BTN_") => key.to_string(),
+                lower => format!("KEY_{}", lower.to_uppercase()),
+            };
+            KeyCode::from_str(&name).map_err(|_| format!("Unknown key {} in {}", key, chord))
+        })
+        .collect()
+}
+
+// e.g. "up", "ctrl+down", "shift+left"
+fn parse_scroll(scroll: &str) -> Result<Action, String> {
+    let (chord, direction) = match scroll.rsplit_once('+') {
+        Some((chord, direction)) => (parse_chord(chord)?, direction),
+        None => (Vec::new(), scroll),
+    };
+    let (x, y) = match direction.trim() {
+        "up" => (0, 1),
+        "down" => (0, -1),
+        "left" => (-1, 0),
+        "right" => (1, 0),
+        _ => return Err(format!("Unknown scroll direction in {}", scroll)),
+    };
+    Ok(Action::Scroll { keys: chord, x, y })
+}
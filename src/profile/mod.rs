@@ -0,0 +1,420 @@
+use std::{collections::HashMap, fs, path::Path, sync::Arc};
+
+use evdev::KeyCode;
+use qwreey_utility_rs::{ErrToString, HeadingError, RwMap};
+use serde::Deserialize;
+
+use crate::{
+    cli::{DeviceList, DeviceListUtil},
+    parse::TiltFormat,
+};
+
+mod action;
+mod mode;
+
+pub use action::{Action, Chord};
+pub use mode::{MODES, Mode, TouchKind};
+
+// --config 로 불러오는 기기별 profile 설정
+
+#[derive(Deserialize, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub profiles: HashMap<String, Profile>,
+    pub groups: HashMap<String, MergeGroup>,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    // Mode on the first connection
+    pub mode: Mode,
+    // Modes visited by mode-cycle, in order
+    pub modes: Vec<Mode>,
+    // Feed the shared devices of this group instead of creating own ones
+    pub merge_group: Option<String>,
+    pub merge_stylus: bool,
+    pub merge_touchpad: bool,
+    // Sub-region of the shared surface this tablet maps into
+    pub region: Region,
+    // Named regions switched with the preset actions
+    pub presets: Vec<Preset>,
+    // Tell the tablet the name of the selected preset
+    pub notify_preset: bool,
+    // Movement scale of the precision mode, relative to its anchor
+    pub precision_scale: f32,
+    pub barrel: BarrelConfig,
+    pub tilt: TiltConfig,
+    // Pad button id -> action
+    pub buttons: HashMap<String, Action>,
+    pub gestures: GestureConfig,
+    pub keyboard: KeyboardConfig,
+    pub haptic: HapticConfig,
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Self {
+            mode: Mode::PenTouchpad,
+            modes: MODES.to_vec(),
+            merge_group: None,
+            merge_stylus: true,
+            merge_touchpad: false,
+            region: Region::default(),
+            presets: Vec::new(),
+            notify_preset: false,
+            precision_scale: 0.25,
+            barrel: BarrelConfig::default(),
+            tilt: TiltConfig::default(),
+            buttons: HashMap::new(),
+            gestures: GestureConfig::default(),
+            keyboard: KeyboardConfig::default(),
+            haptic: HapticConfig::default(),
+        }
+    }
+}
+
+impl Profile {
+    pub fn button(&self, id: u8) -> &Action {
+        self.buttons.get(&id.to_string()).unwrap_or(&Action::Off)
+    }
+
+    // Mode after the current one in the modes list
+    pub fn next_mode(&self, current: Mode) -> Mode {
+        match self.modes.iter().position(|mode| *mode == current) {
+            Some(index) => self.modes[(index + 1) % self.modes.len()],
+            None => self.modes.first().copied().unwrap_or(current),
+        }
+    }
+
+    pub fn preset_index(&self, name: &str) -> Option<usize> {
+        self.presets.iter().position(|preset| preset.name == name)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        self.region.validate()?;
+        for (index, preset) in self.presets.iter().enumerate() {
+            if self.preset_index(&preset.name) != Some(index) {
+                return Err(format!("Preset {} is defined twice", preset.name));
+            }
+            // Sent to the tablet after a u16 length
+            if u16::try_from(preset.name.len()).is_err() {
+                let head = preset.name.chars().take(16).collect::<String>();
+                return Err(format!("Preset name {}... is too long", head));
+            }
+            preset
+                .region
+                .validate()
+                .heading_error(format!("Preset {}: ", preset.name))?;
+        }
+        if !(self.precision_scale > 0.0 && self.precision_scale <= 1.0) {
+            return Err(String::from("Precision scale must be within 0.0 ~ 1.0"));
+        }
+        let barrel = [
+            &self.barrel.press,
+            &self.barrel.double_press,
+            &self.barrel.long_press,
+        ];
+        let actions = self.buttons.values().chain(self.gestures.actions.values());
+        for action in actions.chain(barrel) {
+            if let Action::Preset(name) = action
+                && self.preset_index(name).is_none()
+            {
+                return Err(format!("Unknown preset {}", name));
+            }
+        }
+        for (id, action) in &self.buttons {
+            id.parse::<u8>()
+                .err_to_string()
+                .heading_error(format!("Invalid button id {}: ", id))?;
+            if !action.is_standalone() {
+                return Err(format!("Button {} can not use {:?}", id, action));
+            }
+        }
+        for (gesture, action) in &self.gestures.actions {
+            if !action.is_standalone() {
+                return Err(format!("Gesture {} can not use {:?}", gesture, action));
+            }
+        }
+        for code in self.keyboard.keymap.keys() {
+            code.parse::<u16>()
+                .err_to_string()
+                .heading_error(format!("Invalid keycode {}: ", code))?;
+        }
+        for character in self.keyboard.layout.keys() {
+            if character.chars().count() != 1 {
+                return Err(format!(
+                    "Layout key {} must be a single character",
+                    character
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct BarrelConfig {
+    pub press: Action,
+    pub double_press: Action,
+    pub long_press: Action,
+    // Milliseconds, compared with the stylus timestamps
+    pub double_press_timeout: i32,
+    pub long_press_timeout: i32,
+}
+
+impl Default for BarrelConfig {
+    fn default() -> Self {
+        Self {
+            press: Action::Eraser,
+            double_press: Action::Stylus,
+            long_press: Action::Off,
+            double_press_timeout: 800,
+            long_press_timeout: 500,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct TiltConfig {
+    // Overrides the format declared by the tablet (xy, polar)
+    pub format: Option<TiltFormat>,
+    // Degrees added after the conversion
+    pub offset_x: f32,
+    pub offset_y: f32,
+    pub invert_x: bool,
+    pub invert_y: bool,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct GestureConfig {
+    // Keep sending touches to the touchpad while recognizing gestures
+    pub passthrough: bool,
+    // Milliseconds
+    pub tap_timeout: u64,
+    pub long_press_timeout: u64,
+    // Touch units a finger may move while still tapping
+    pub tap_distance: f32,
+    // Fraction of the tablet size
+    pub swipe_distance: f32,
+    // Scale change per pinch step
+    pub pinch_step: f32,
+    // Degrees per rotate step
+    pub rotate_step: f32,
+    // Gesture name (tap-2, long-press-1, swipe-3-left, pinch-in, rotate-cw, ...) -> action
+    pub actions: HashMap<String, Action>,
+}
+
+impl Default for GestureConfig {
+    fn default() -> Self {
+        Self {
+            passthrough: true,
+            tap_timeout: 250,
+            long_press_timeout: 600,
+            tap_distance: 30.0,
+            swipe_distance: 0.15,
+            pinch_step: 0.15,
+            rotate_step: 15.0,
+            actions: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeyboardConfig {
+    // Android keycode -> keys, overrides the built-in table
+    pub keymap: HashMap<String, Chord>,
+    // Character -> keys, overrides the US layout
+    pub layout: HashMap<String, Chord>,
+    // Starts a hex code point input for characters without keys, "off" to drop them
+    pub unicode_input: Chord,
+}
+
+impl Default for KeyboardConfig {
+    fn default() -> Self {
+        Self {
+            keymap: HashMap::new(),
+            layout: HashMap::new(),
+            unicode_input: Chord(vec![
+                KeyCode::KEY_LEFTCTRL,
+                KeyCode::KEY_LEFTSHIFT,
+                KeyCode::KEY_U,
+            ]),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct HapticConfig {
+    // Milliseconds
+    pub duration: u16,
+    pub amplitude: u8,
+    pub on_gesture: bool,
+    pub on_mode_switch: bool,
+    // Vibrate when the pressure goes over this value
+    pub pressure_click: Option<i16>,
+}
+
+impl Default for HapticConfig {
+    fn default() -> Self {
+        Self {
+            duration: 20,
+            amplitude: 255,
+            on_gesture: false,
+            on_mode_switch: false,
+            pressure_click: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct MergeGroup {
+    pub width: u16,
+    pub height: u16,
+}
+
+impl Default for MergeGroup {
+    fn default() -> Self {
+        Self {
+            width: i16::MAX as u16,
+            height: i16::MAX as u16,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Preset {
+    pub name: String,
+    #[serde(default)]
+    pub region: Region,
+}
+
+// Normalized rectangle, 0.0 ~ 1.0 of the target surface
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Region {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Default for Region {
+    fn default() -> Self {
+        Self {
+            x: 0.0,
+            y: 0.0,
+            width: 1.0,
+            height: 1.0,
+        }
+    }
+}
+
+impl Region {
+    // Map a point of the source size into this region of the target size
+    pub fn map(&self, x: i32, y: i32, source: (u16, u16), target: (u16, u16)) -> (i32, i32) {
+        let map_axis = |value: i32, offset: f32, scale: f32, source: u16, target: u16| {
+            let ratio = value as f32 / source.max(1) as f32;
+            ((offset + ratio * scale) * target as f32)
+                .round()
+                .clamp(0.0, target as f32) as i32
+        };
+        (
+            map_axis(x, self.x, self.width, source.0, target.0),
+            map_axis(y, self.y, self.height, source.1, target.1),
+        )
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let in_range = |value: f32| (0.0..=1.0).contains(&value);
+        if !in_range(self.x)
+            || !in_range(self.y)
+            || self.width <= 0.0
+            || self.height <= 0.0
+            || !in_range(self.x + self.width)
+            || !in_range(self.y + self.height)
+        {
+            return Err(String::from("Region must be inside of 0.0 ~ 1.0"));
+        }
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path)
+            .err_to_string()
+            .heading_error(format!("Failed to read {}: ", path.display()))?;
+        toml::from_str(&text)
+            .err_to_string()
+            .heading_error(format!("Failed to parse {}: ", path.display()))
+    }
+
+    // Check every profile and the profiles referenced from the device list
+    pub fn validate(&self, devices: &DeviceList) -> Result<(), String> {
+        for (name, profile) in &self.profiles {
+            profile
+                .validate()
+                .heading_error(format!("Profile {}: ", name))?;
+        }
+        for (name, group) in &self.groups {
+            // Positions are carried as i16 on the wire
+            if group.width == 0
+                || group.height == 0
+                || group.width > i16::MAX as u16
+                || group.height > i16::MAX as u16
+            {
+                return Err(format!(
+                    "Group {}: size must be within 1 ~ {}",
+                    name,
+                    i16::MAX
+                ));
+            }
+        }
+        for device in devices {
+            if let Some(ref profile) = device.profile
+                && !self.profiles.contains_key(profile)
+            {
+                return Err(format!(
+                    "Device {} uses undefined profile {}",
+                    device.name, profile
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn profile(&self, name: Option<&str>) -> Profile {
+        name.and_then(|name| self.profiles.get(name))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn group(&self, name: &str) -> MergeGroup {
+        self.groups.get(name).cloned().unwrap_or_default()
+    }
+}
+
+// Profile name given with the device argument (DeviceName:port:profile)
+pub fn profile_name_of(userdata: &Arc<RwMap>, serial: &str) -> Option<String> {
+    userdata
+        .get_of::<DeviceList>()
+        .unwrap()
+        .get_device(serial)
+        .and_then(|device| device.profile.clone())
+}
+
+pub fn profile_of(userdata: &Arc<RwMap>, serial: &str) -> Profile {
+    let profile_name = profile_name_of(userdata, serial);
+    userdata
+        .get_of::<Config>()
+        .unwrap()
+        .profile(profile_name.as_deref())
+}
@@ -0,0 +1,74 @@
+use std::{fmt, str::FromStr};
+
+use serde::Deserialize;
+
+// 태블릿이 어떤 가상 장치로 동작할지 결정하는 모드
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(try_from = "String")]
+pub enum Mode {
+    Pen,
+    Touchpad,
+    Touchscreen,
+    PenTouchpad,
+    PenTouchscreen,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TouchKind {
+    Touchpad,
+    Touchscreen,
+}
+
+pub const MODES: [Mode; 5] = [
+    Mode::PenTouchpad,
+    Mode::Pen,
+    Mode::Touchpad,
+    Mode::Touchscreen,
+    Mode::PenTouchscreen,
+];
+
+impl Mode {
+    pub fn has_stylus(&self) -> bool {
+        matches!(self, Mode::Pen | Mode::PenTouchpad | Mode::PenTouchscreen)
+    }
+
+    pub fn touch(&self) -> Option<TouchKind> {
+        match self {
+            Mode::Touchpad | Mode::PenTouchpad => Some(TouchKind::Touchpad),
+            Mode::Touchscreen | Mode::PenTouchscreen => Some(TouchKind::Touchscreen),
+            Mode::Pen => None,
+        }
+    }
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Mode::Pen => "pen",
+            Mode::Touchpad => "touchpad",
+            Mode::Touchscreen => "touchscreen",
+            Mode::PenTouchpad => "pen+touchpad",
+            Mode::PenTouchscreen => "pen+touchscreen",
+        })
+    }
+}
+
+impl FromStr for Mode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        MODES
+            .into_iter()
+            .find(|mode| mode.to_string() == value)
+            .ok_or_else(|| format!("Unknown mode {}", value))
+    }
+}
+
+impl TryFrom<String> for Mode {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
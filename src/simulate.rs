@@ -0,0 +1,308 @@
+use std::{collections::VecDeque, f32::consts::PI};
+
+use bytebuffer::{ByteBuffer, Endian};
+use futures_util::{SinkExt, StreamExt};
+use qwreey_utility_rs::ErrToString;
+use tokio::{
+    net::{TcpListener, TcpStream},
+    time::{Duration, interval},
+};
+use tokio_websockets::{Message, ServerBuilder};
+
+use crate::{
+    cli::{SimulateArgs, Stroke},
+    trace::parse_trace,
+};
+
+// 안드로이드 기기 없이 Pendroid 프로토콜을 말하는 가짜 태블릿.
+// 로컬 WebSocket 서버로 동작하며, 합성한 stroke 나 기록된 trace 를 보냅니다.
+// --no-adb 로 실행한 데몬의 device 를 이 포트로 지정하면 전체 경로를 시험할 수 있습니다.
+
+const PRESSURE_MAX: u16 = 4096;
+const TILT_MAX: i16 = 9000;
+
+struct Sample {
+    x: f32,
+    y: f32,
+    // 0 ~ 1
+    pressure: f32,
+    tilt_x: i16,
+    tilt_y: i16,
+}
+
+// (slot, x, y) of a finger down
+type Contact = (u8, f32, f32);
+
+// Encodes the synthetic events, one message each
+struct Generator {
+    width: f32,
+    height: f32,
+    interval: i32,
+    timestamp: i32,
+    tracking_id: i32,
+    messages: Vec<Vec<u8>>,
+}
+
+fn new_buf(event_type: u8) -> ByteBuffer {
+    let mut buf = ByteBuffer::new();
+    buf.set_endian(Endian::LittleEndian);
+    buf.write_u8(event_type);
+    buf
+}
+
+impl Generator {
+    fn new(args: &SimulateArgs) -> Self {
+        Self {
+            width: args.width as f32,
+            height: args.height as f32,
+            interval: args.interval as i32,
+            timestamp: 0,
+            tracking_id: 0,
+            messages: Vec::new(),
+        }
+    }
+
+    // Init with the capabilities of a 10 units/mm screen
+    fn init(&self) -> Vec<u8> {
+        let mut buf = new_buf(0x0);
+        buf.write_u16(self.width as u16);
+        buf.write_u16(self.height as u16);
+        buf.write_u8(0x0);
+        buf.write_u16(PRESSURE_MAX);
+        buf.write_u16(self.width as u16 / 10);
+        buf.write_u16(self.height as u16 / 10);
+        buf.write_i16(TILT_MAX);
+        buf.into_vec()
+    }
+
+    fn push(&mut self, buf: ByteBuffer) {
+        self.messages.push(buf.into_vec());
+        self.timestamp = self.timestamp.wrapping_add(self.interval);
+    }
+
+    fn pen(&mut self, hover: bool, down: bool, sample: &Sample) {
+        let mut buf = new_buf(0x1);
+        buf.write_u8(down as u8 | (hover as u8) << 2);
+        buf.write_i16((sample.pressure.clamp(0.0, 1.0) * PRESSURE_MAX as f32) as i16);
+        buf.write_i16(sample.tilt_x);
+        buf.write_i16(sample.tilt_y);
+        buf.write_i16(sample.x as i16);
+        buf.write_i16(sample.y as i16);
+        buf.write_i32(self.timestamp);
+        self.push(buf);
+    }
+
+    // Every contact down, then the released slots
+    fn fingers(&mut self, contacts: &[Contact], released: &[u8]) {
+        let mut buf = new_buf(0x5);
+        buf.write_i32(self.timestamp);
+        buf.write_u8((contacts.len() + released.len()) as u8);
+        for (slot, x, y) in contacts {
+            buf.write_u8(*slot);
+            buf.write_u8(1);
+            buf.write_u8(contacts.len() as u8);
+            buf.write_i32(self.tracking_id + *slot as i32);
+            buf.write_i16(*x as i16);
+            buf.write_i16(*y as i16);
+        }
+        for slot in released {
+            buf.write_u8(*slot);
+            buf.write_u8(0);
+            buf.write_u8(contacts.len() as u8);
+            buf.write_i32(-1);
+            buf.write_i16(-1);
+            buf.write_i16(-1);
+        }
+        self.push(buf);
+    }
+
+    // Approach, draw every sample, lift and leave
+    fn stroke(&mut self, samples: &[Sample]) {
+        let (Some(first), Some(last)) = (samples.first(), samples.last()) else {
+            return;
+        };
+        let hover = |sample: &Sample| Sample {
+            pressure: 0.0,
+            ..*sample
+        };
+        for _ in 0..3 {
+            self.pen(true, false, &hover(first));
+        }
+        for sample in samples {
+            self.pen(true, true, sample);
+        }
+        self.pen(true, false, &hover(last));
+        self.pen(false, false, &hover(last));
+    }
+
+    fn line(&mut self) {
+        let samples = (0..=60)
+            .map(|step| {
+                let t = step as f32 / 60.0;
+                Sample {
+                    x: self.width * (0.2 + 0.6 * t),
+                    y: self.height * 0.5,
+                    pressure: 0.5,
+                    tilt_x: 2000,
+                    tilt_y: 0,
+                }
+            })
+            .collect::<Vec<_>>();
+        self.stroke(&samples);
+    }
+
+    // Circle with the pen leaning outwards
+    fn circle(&mut self) {
+        let radius = self.width.min(self.height) * 0.3;
+        let samples = (0..=90)
+            .map(|step| {
+                let angle = step as f32 / 90.0 * 2.0 * PI;
+                Sample {
+                    x: self.width * 0.5 + angle.cos() * radius,
+                    y: self.height * 0.5 + angle.sin() * radius,
+                    pressure: 0.6,
+                    tilt_x: (angle.cos() * 3000.0) as i16,
+                    tilt_y: (angle.sin() * 3000.0) as i16,
+                }
+            })
+            .collect::<Vec<_>>();
+        self.stroke(&samples);
+    }
+
+    // Diagonal stroke, pressure rising to the maximum and back
+    fn pressure(&mut self) {
+        let samples = (0..=80)
+            .map(|step| {
+                let t = step as f32 / 80.0;
+                Sample {
+                    x: self.width * (0.2 + 0.6 * t),
+                    y: self.height * (0.3 + 0.4 * t),
+                    pressure: (t * PI).sin(),
+                    tilt_x: 0,
+                    tilt_y: 0,
+                }
+            })
+            .collect::<Vec<_>>();
+        self.stroke(&samples);
+    }
+
+    // Two fingers moving towards each other
+    fn pinch(&mut self) {
+        let (center_x, center_y) = (self.width * 0.5, self.height * 0.5);
+        for step in 0..=40 {
+            let distance = self.width * (0.3 - 0.22 * step as f32 / 40.0);
+            self.fingers(
+                &[
+                    (0, center_x - distance, center_y),
+                    (1, center_x + distance, center_y),
+                ],
+                &[],
+            );
+        }
+        self.fingers(&[], &[0, 1]);
+        self.tracking_id += 2;
+    }
+
+    fn strokes(&mut self, stroke: Stroke) -> Vec<Vec<u8>> {
+        match stroke {
+            Stroke::Line => self.line(),
+            Stroke::Circle => self.circle(),
+            Stroke::Pressure => self.pressure(),
+            Stroke::Pinch => self.pinch(),
+            Stroke::All => {
+                self.line();
+                self.circle();
+                self.pressure();
+                self.pinch();
+            }
+        }
+        std::mem::take(&mut self.messages)
+    }
+}
+
+// Messages of one pass, the init included
+fn next_pass(
+    args: &SimulateArgs,
+    script: &Option<Vec<Vec<u8>>>,
+    generator: &mut Generator,
+) -> Vec<Vec<u8>> {
+    match script {
+        Some(messages) => messages.clone(),
+        None => generator.strokes(args.stroke),
+    }
+}
+
+async fn session(
+    stream: TcpStream,
+    args: &SimulateArgs,
+    script: &Option<Vec<Vec<u8>>>,
+) -> Result<(), String> {
+    let (_, mut client) = ServerBuilder::new().accept(stream).await.err_to_string()?;
+
+    let mut generator = Generator::new(args);
+    let mut pending = VecDeque::new();
+    // A script carries its own init
+    if script.is_none() {
+        pending.push_back(generator.init());
+    }
+    pending.extend(next_pass(args, script, &mut generator));
+
+    let mut ticker = interval(Duration::from_millis(args.interval.max(1)));
+    loop {
+        tokio::select! {
+            _ = ticker.tick(), if !pending.is_empty() => {
+                let message = pending.pop_front().unwrap();
+                client.send(Message::binary(message)).await.err_to_string()?;
+                if pending.is_empty() {
+                    if args.repeat {
+                        pending.extend(next_pass(args, script, &mut generator));
+                    } else {
+                        tracing::info!("Finished sending, waiting for the host to disconnect");
+                    }
+                }
+            }
+            item = client.next() => {
+                let Some(item) = item else {
+                    return Ok(());
+                };
+                let msg = item.err_to_string()?;
+                if msg.is_close() {
+                    return Ok(());
+                }
+                let kind = match msg.as_payload().first() {
+                    Some(0x80) => "haptic",
+                    Some(0x81) => "preset",
+                    _ => "unknown",
+                };
+                tracing::info!("Host sent {} message ({} bytes)", kind, msg.as_payload().len());
+            }
+        }
+    }
+}
+
+pub async fn serve(args: SimulateArgs) -> Result<(), String> {
+    let script = match args.script {
+        Some(ref path) => Some(parse_trace(
+            &std::fs::read_to_string(path).err_to_string()?,
+        )?),
+        None => None,
+    };
+
+    let listener = TcpListener::bind(("127.0.0.1", args.port))
+        .await
+        .err_to_string()?;
+    tracing::info!("Simulating a tablet on ws://127.0.0.1:{}", args.port);
+
+    loop {
+        let (stream, address) = listener.accept().await.err_to_string()?;
+        tracing::info!("Host connected from {}", address);
+        let args = args.clone();
+        let script = script.clone();
+        tokio::spawn(async move {
+            if let Err(err) = session(stream, &args, &script).await {
+                tracing::error!("Simulated session failed: {}", err);
+            }
+            tracing::info!("Host disconnected");
+        });
+    }
+}
@@ -0,0 +1,102 @@
+use std::{
+    fmt::Write as _,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use qwreey_utility_rs::ErrToString;
+
+// 태블릿이 보낸 websocket 메시지를 기록하고 다시 읽는 trace 형식.
+// 한 줄에 메시지 하나를 hex 로 쓰며, '#' 뒤는 주석입니다.
+// tests/traces 의 golden trace 도 같은 형식을 사용합니다.
+
+// Writes every binary message of one connection
+pub struct TraceRecorder {
+    writer: BufWriter<File>,
+}
+
+impl TraceRecorder {
+    // New <serial>-<unix seconds>.trace file in the directory
+    pub fn create(dir: &Path, serial: &str) -> Result<Self, String> {
+        let seconds = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .err_to_string()?
+            .as_secs();
+        let name = format!("{}-{}.trace", serial.replace(['/', ':'], "_"), seconds);
+        let mut writer = BufWriter::new(File::create(dir.join(name)).err_to_string()?);
+        writeln!(writer, "# recorded from {}", serial).err_to_string()?;
+        Ok(Self { writer })
+    }
+
+    pub fn record(&mut self, payload: &[u8]) -> Result<(), String> {
+        writeln!(self.writer, "{}", encode_message(payload)).err_to_string()
+    }
+}
+
+impl Drop for TraceRecorder {
+    fn drop(&mut self) {
+        let _ = self.writer.flush();
+    }
+}
+
+pub fn encode_message(payload: &[u8]) -> String {
+    payload.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Messages of a trace, whitespace between the hex digits is ignored
+pub fn parse_trace(text: &str) -> Result<Vec<Vec<u8>>, String> {
+    let mut messages = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default();
+        let digits = line
+            .chars()
+            .filter(|char| !char.is_whitespace())
+            .collect::<Vec<_>>();
+        if digits.is_empty() {
+            continue;
+        }
+        if digits.len() % 2 != 0 {
+            return Err(format!("Line {}: odd number of hex digits", index + 1));
+        }
+        let message = digits
+            .chunks(2)
+            .map(|pair| {
+                u8::from_str_radix(&pair.iter().collect::<String>(), 16)
+                    .map_err(|err| format!("Line {}: {}", index + 1, err))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        messages.push(message);
+    }
+    Ok(messages)
+}
+
+// Readable dump of emitted frames, each closed by SYN_REPORT
+#[cfg(target_os = "linux")]
+pub fn format_frames(device: &str, frames: &[Vec<evdev::InputEvent>]) -> String {
+    use evdev::EventSummary;
+
+    let mut text = String::new();
+    for frame in frames {
+        let _ = writeln!(text, "[{}]", device);
+        for event in frame {
+            let _ = match EventSummary::from(*event) {
+                EventSummary::Key(_, code, value) => writeln!(text, "{:?} {}", code, value),
+                EventSummary::AbsoluteAxis(_, code, value) => {
+                    writeln!(text, "{:?} {}", code, value)
+                }
+                EventSummary::Misc(_, code, value) => writeln!(text, "{:?} {}", code, value),
+                _ => writeln!(
+                    text,
+                    "{} {} {}",
+                    event.event_type().0,
+                    event.code(),
+                    event.value()
+                ),
+            };
+        }
+        text.push_str("SYN_REPORT\n");
+    }
+    text
+}
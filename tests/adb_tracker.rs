@@ -0,0 +1,285 @@
+// 가짜 adb server 를 상대로 adb tracker 를 실행해
+// 연결, 끊김, offline, unauthorized, tracker 재시작 상황을 확인합니다.
+
+mod common;
+
+use std::{
+    net::TcpListener,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use clap::Parser;
+use pendroid_linux::{
+    DeviceMap, WorkerIdMap,
+    adb_client::{AdbClient, AdbServerClient},
+    adb_tracker::run_adb_tracker,
+    backoff::{BackoffConfig, Status},
+    cli::Command,
+    metrics::Metrics,
+    profile::Config,
+};
+use qwreey_utility_rs::RwMap;
+use tokio::runtime::Runtime;
+
+use common::fake_adb::FakeAdb;
+
+const SERIAL: &str = "tablet";
+
+struct Tracker {
+    adb: FakeAdb,
+    userdata: Arc<RwMap>,
+    port: i32,
+    runtime: Option<Runtime>,
+}
+
+impl Tracker {
+    // Tracker of the single SERIAL device, connected to a new fake server
+    fn start() -> Self {
+        let adb = FakeAdb::start();
+        // Nothing listens here, so the websocket worker keeps retrying
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port() as i32;
+        let command = Command::parse_from([
+            "pendroid-linux",
+            "--devices",
+            &format!("{}:{}", SERIAL, port),
+        ]);
+
+        let userdata = Arc::new(RwMap::new());
+        userdata.insert_of(command.clone());
+        userdata.insert_of(Config::default());
+        userdata.insert_of(Arc::new(Metrics::default()));
+        userdata.insert_of(BackoffConfig::new(20, 200, 0.0).unwrap());
+        userdata.insert_of(Status::default());
+        userdata.insert("worker_id_map", WorkerIdMap::new());
+        userdata.insert_of(command.devices);
+        userdata.insert("device_map", DeviceMap::new());
+        userdata.insert_of::<Arc<dyn AdbClient>>(Arc::new(AdbServerClient::new(adb.address())));
+
+        let runtime = Runtime::new().unwrap();
+        {
+            let _guard = runtime.enter();
+            run_adb_tracker(userdata.clone());
+        }
+        wait_until("tracker connected", || adb.tracks() == 1);
+
+        Self {
+            adb,
+            userdata,
+            port,
+            runtime: Some(runtime),
+        }
+    }
+
+    fn local(&self) -> String {
+        format!("tcp:{}", self.port)
+    }
+
+    fn forwarded(&self) -> bool {
+        self.adb
+            .forwards()
+            .contains(&(SERIAL.to_string(), self.local(), String::from("tcp:23227")))
+    }
+
+    fn status(&self) -> String {
+        self.userdata.get_of::<Status>().unwrap().summary()
+    }
+
+    fn worker_id(&self) -> Option<tokio::time::Instant> {
+        self.userdata
+            .get::<WorkerIdMap>("worker_id_map")
+            .unwrap()
+            .get(SERIAL)
+            .copied()
+    }
+
+    fn has_worker(&self) -> bool {
+        self.userdata
+            .get::<DeviceMap>("device_map")
+            .unwrap()
+            .contains_key(SERIAL)
+            && self
+                .userdata
+                .get::<WorkerIdMap>("worker_id_map")
+                .unwrap()
+                .contains_key(SERIAL)
+    }
+}
+
+impl Drop for Tracker {
+    fn drop(&mut self) {
+        // The blocking tracker loop never returns by itself
+        if let Some(runtime) = self.runtime.take() {
+            runtime.shutdown_background();
+        }
+    }
+}
+
+fn wait_until(what: &str, condition: impl Fn() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !condition() {
+        assert!(Instant::now() < deadline, "Timed out waiting for {}", what);
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+// Let the tracker handle the latest list before checking that nothing happened
+fn settle() {
+    thread::sleep(Duration::from_millis(300));
+}
+
+#[test]
+fn connect_forwards_and_starts_worker() {
+    let tracker = Tracker::start();
+    tracker.adb.set_device(SERIAL, "device");
+
+    wait_until("forward", || tracker.forwarded());
+    wait_until("worker", || tracker.has_worker());
+}
+
+#[test]
+fn offline_removes_forward_and_worker() {
+    let tracker = Tracker::start();
+    tracker.adb.set_device(SERIAL, "device");
+    wait_until("worker", || tracker.has_worker());
+
+    tracker.adb.set_device(SERIAL, "offline");
+    wait_until("forward removal", || {
+        tracker
+            .adb
+            .removed()
+            .contains(&(SERIAL.to_string(), tracker.local()))
+    });
+    wait_until("worker stop", || !tracker.has_worker());
+    assert!(!tracker.forwarded());
+}
+
+#[test]
+fn reconnect_after_offline() {
+    let tracker = Tracker::start();
+    tracker.adb.set_device(SERIAL, "device");
+    wait_until("worker", || tracker.has_worker());
+    tracker.adb.set_device(SERIAL, "offline");
+    wait_until("worker stop", || !tracker.has_worker());
+
+    tracker.adb.set_device(SERIAL, "device");
+    wait_until("forward", || tracker.forwarded());
+    wait_until("worker", || tracker.has_worker());
+}
+
+#[test]
+fn unauthorized_is_not_forwarded() {
+    let tracker = Tracker::start();
+    tracker.adb.set_device(SERIAL, "unauthorized");
+    settle();
+
+    assert!(tracker.adb.forwards().is_empty());
+    assert!(!tracker.has_worker());
+}
+
+#[test]
+fn unknown_device_is_ignored() {
+    let tracker = Tracker::start();
+    tracker.adb.set_device("phone", "device");
+    settle();
+
+    assert!(tracker.adb.forwards().is_empty());
+    assert!(!tracker.has_worker());
+}
+
+#[test]
+fn tracker_restarts_after_crash() {
+    let tracker = Tracker::start();
+    tracker.adb.set_device(SERIAL, "device");
+    wait_until("worker", || tracker.has_worker());
+
+    tracker.adb.crash_trackers();
+    wait_until("tracker reconnected", || tracker.adb.tracks() == 2);
+    wait_until("worker", || tracker.has_worker());
+    assert!(tracker.forwarded());
+}
+
+#[test]
+fn status_shows_retrying_worker() {
+    let tracker = Tracker::start();
+    tracker.adb.set_device(SERIAL, "device");
+
+    // The websocket port is closed, so the worker backs off
+    wait_until("retry status", || {
+        let status = tracker.status();
+        status.starts_with("adb=connected ") && status.contains("tablet=retrying:")
+    });
+
+    tracker.adb.set_device(SERIAL, "offline");
+    wait_until("status removal", || tracker.status() == "adb=connected");
+}
+
+#[test]
+fn leaving_device_state_stops_worker() {
+    for state in [
+        "offline",
+        "unauthorized",
+        "recovery",
+        "sideload",
+        "bootloader",
+    ] {
+        let tracker = Tracker::start();
+        tracker.adb.set_device(SERIAL, "device");
+        wait_until("worker", || tracker.has_worker());
+
+        tracker.adb.set_device(SERIAL, state);
+        wait_until(&format!("worker stop on {}", state), || {
+            !tracker.has_worker()
+        });
+    }
+}
+
+#[test]
+fn vanished_device_stops_worker() {
+    let tracker = Tracker::start();
+    tracker.adb.set_device(SERIAL, "device");
+    wait_until("worker", || tracker.has_worker());
+
+    tracker.adb.remove_device(SERIAL);
+    wait_until("worker stop", || !tracker.has_worker());
+}
+
+#[test]
+fn other_device_change_keeps_worker() {
+    let tracker = Tracker::start();
+    tracker.adb.set_device(SERIAL, "device");
+    wait_until("worker", || tracker.has_worker());
+    let worker = tracker.worker_id();
+
+    tracker.adb.set_device("phone", "device");
+    tracker.adb.set_device("phone", "offline");
+    settle();
+    assert_eq!(tracker.worker_id(), worker);
+}
+
+#[test]
+fn unknown_state_is_skipped() {
+    let tracker = Tracker::start();
+    tracker.adb.set_device("phone", "mystery");
+    tracker.adb.set_device(SERIAL, "device");
+
+    wait_until("worker", || tracker.has_worker());
+    assert_eq!(tracker.adb.tracks(), 1);
+}
+
+#[test]
+fn device_gone_while_tracker_down_is_disconnected() {
+    let tracker = Tracker::start();
+    tracker.adb.set_device(SERIAL, "device");
+    wait_until("worker", || tracker.has_worker());
+
+    tracker.adb.crash_trackers();
+    tracker.adb.remove_device(SERIAL);
+    wait_until("tracker reconnected", || tracker.adb.tracks() == 2);
+    wait_until("worker stop", || !tracker.has_worker());
+}
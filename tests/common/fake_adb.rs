@@ -0,0 +1,241 @@
+use std::{
+    io::{Read, Write},
+    net::{SocketAddrV4, TcpListener, TcpStream},
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::Duration,
+};
+
+// adb host protocol 중 tracker 가 쓰는 부분만 구현한 가짜 adb server.
+// track-devices 와 host-serial 의 forward, killforward 를 처리합니다.
+// std thread 위에서 돌기 때문에 tokio runtime 이 끝나도 남아 있습니다.
+
+#[derive(Default)]
+struct State {
+    // (serial, state) in listing order
+    devices: Vec<(String, String)>,
+    // Bumped on every change of the device list
+    version: u64,
+    // Bumped to drop every track-devices connection
+    generation: u64,
+    tracks: usize,
+    // (serial, local, remote)
+    forwards: Vec<(String, String, String)>,
+    // (serial, local)
+    removed: Vec<(String, String)>,
+}
+
+#[derive(Clone)]
+pub struct FakeAdb {
+    address: SocketAddrV4,
+    state: Arc<(Mutex<State>, Condvar)>,
+}
+
+impl FakeAdb {
+    pub fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = match listener.local_addr().unwrap() {
+            std::net::SocketAddr::V4(address) => address,
+            _ => unreachable!(),
+        };
+        let server = Self {
+            address,
+            state: Arc::new((Mutex::new(State::default()), Condvar::new())),
+        };
+
+        let accepting = server.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let connection = accepting.clone();
+                thread::spawn(move || {
+                    let _ = connection.serve(stream);
+                });
+            }
+        });
+        server
+    }
+
+    pub fn address(&self) -> SocketAddrV4 {
+        self.address
+    }
+
+    fn update(&self, change: impl FnOnce(&mut State)) {
+        let (state, changed) = &*self.state;
+        change(&mut state.lock().unwrap());
+        changed.notify_all();
+    }
+
+    fn read<T>(&self, read: impl FnOnce(&State) -> T) -> T {
+        read(&self.state.0.lock().unwrap())
+    }
+
+    // Add the device or change its state
+    pub fn set_device(&self, serial: &str, device_state: &str) {
+        self.update(|state| {
+            match state.devices.iter_mut().find(|(name, _)| name == serial) {
+                Some(device) => device.1 = device_state.to_string(),
+                None => state
+                    .devices
+                    .push((serial.to_string(), device_state.to_string())),
+            }
+            state.version += 1;
+        });
+    }
+
+    pub fn remove_device(&self, serial: &str) {
+        self.update(|state| {
+            state.devices.retain(|(name, _)| name != serial);
+            // adb drops the forwards of a vanished device
+            state.forwards.retain(|(name, _, _)| name != serial);
+            state.version += 1;
+        });
+    }
+
+    // Close every track-devices connection, as a restarting adb server does
+    pub fn crash_trackers(&self) {
+        self.update(|state| state.generation += 1);
+    }
+
+    // Number of track-devices connections accepted so far
+    pub fn tracks(&self) -> usize {
+        self.read(|state| state.tracks)
+    }
+
+    // (serial, local, remote) of the active forwards
+    pub fn forwards(&self) -> Vec<(String, String, String)> {
+        self.read(|state| state.forwards.clone())
+    }
+
+    // (serial, local) of every removed forward
+    pub fn removed(&self) -> Vec<(String, String)> {
+        self.read(|state| state.removed.clone())
+    }
+
+    fn serve(&self, mut stream: TcpStream) -> std::io::Result<()> {
+        let request = read_request(&mut stream)?;
+        if request == "host:track-devices" {
+            stream.write_all(b"OKAY")?;
+            return self.track(stream);
+        }
+        let Some(rest) = request.strip_prefix("host-serial:") else {
+            return fail(&mut stream, "unknown host service");
+        };
+
+        // Serials may hold ':', so split on the service name
+        let (serial, service) = if let Some((serial, local)) = rest.split_once(":killforward:") {
+            (serial, Err(local))
+        } else if let Some((serial, rule)) = rest.split_once(":forward:") {
+            (serial, Ok(rule))
+        } else {
+            return fail(&mut stream, "unknown host service");
+        };
+        // Forwarding needs the device online, removal any listed state
+        if !self.is_listed(serial, service.is_ok()) {
+            return fail(&mut stream, &format!("device '{}' not found", serial));
+        }
+        // First OKAY for the device, the second for the result
+        stream.write_all(b"OKAY")?;
+
+        match service {
+            Ok(rule) => {
+                let Some((local, remote)) = rule.split_once(';') else {
+                    return fail(&mut stream, "malformed forward rule");
+                };
+                let forward = (serial.to_string(), local.to_string(), remote.to_string());
+                self.update(|state| {
+                    state.forwards.retain(|(_, bound, _)| bound != local);
+                    state.forwards.push(forward);
+                });
+                stream.write_all(b"OKAY")
+            }
+            Err(local) => self.kill_forward(&mut stream, serial, local),
+        }
+    }
+
+    fn is_listed(&self, serial: &str, online: bool) -> bool {
+        self.read(|state| {
+            state
+                .devices
+                .iter()
+                .any(|(name, device_state)| name == serial && (!online || device_state == "device"))
+        })
+    }
+
+    fn kill_forward(
+        &self,
+        stream: &mut TcpStream,
+        serial: &str,
+        local: &str,
+    ) -> std::io::Result<()> {
+        let (state, _) = &*self.state;
+        let mut state = state.lock().unwrap();
+        let found = state
+            .forwards
+            .iter()
+            .position(|(name, bound, _)| name == serial && bound == local);
+        state.removed.push((serial.to_string(), local.to_string()));
+        match found {
+            Some(index) => {
+                state.forwards.remove(index);
+                drop(state);
+                stream.write_all(b"OKAY")
+            }
+            None => {
+                drop(state);
+                fail(stream, &format!("listener '{}' not found", local))
+            }
+        }
+    }
+
+    // Send the device list now and again on every change
+    fn track(&self, mut stream: TcpStream) -> std::io::Result<()> {
+        let (state, changed) = &*self.state;
+        let mut guard = state.lock().unwrap();
+        guard.tracks += 1;
+        let generation = guard.generation;
+        let mut sent = None;
+        loop {
+            if guard.generation != generation {
+                return Ok(());
+            }
+            if sent != Some(guard.version) {
+                sent = Some(guard.version);
+                let list = device_list(&guard.devices);
+                drop(guard);
+                write_message(&mut stream, &list)?;
+                guard = state.lock().unwrap();
+                continue;
+            }
+            guard = changed
+                .wait_timeout(guard, Duration::from_millis(100))
+                .unwrap()
+                .0;
+        }
+    }
+}
+
+fn device_list(devices: &[(String, String)]) -> String {
+    devices
+        .iter()
+        .map(|(serial, state)| format!("{}\t{}\n", serial, state))
+        .collect()
+}
+
+fn read_request(stream: &mut TcpStream) -> std::io::Result<String> {
+    let mut length = [0u8; 4];
+    stream.read_exact(&mut length)?;
+    let length = usize::from_str_radix(&String::from_utf8_lossy(&length), 16)
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
+    let mut request = vec![0u8; length];
+    stream.read_exact(&mut request)?;
+    Ok(String::from_utf8_lossy(&request).to_string())
+}
+
+fn write_message(stream: &mut TcpStream, message: &str) -> std::io::Result<()> {
+    stream.write_all(format!("{:04x}{}", message.len(), message).as_bytes())
+}
+
+fn fail(stream: &mut TcpStream, message: &str) -> std::io::Result<()> {
+    stream.write_all(b"FAIL")?;
+    write_message(stream, message)
+}
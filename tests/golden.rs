@@ -0,0 +1,150 @@
+// tests/traces 의 trace 를 stylus / finger backend 로 재생하고
+// 나온 evdev frame 을 같은 이름의 .expected 파일과 비교합니다.
+// PENDROID_BLESS=1 로 실행하면 .expected 를 현재 출력으로 다시 씁니다.
+#![cfg(target_os = "linux")]
+
+use std::{fs, path::Path};
+
+use bytebuffer::{ByteReader, Endian};
+use evdev::{EventType, InputEvent, MiscCode};
+use pendroid_linux::{
+    backend::{CaptureSink, FingerBackend, StylusBackend},
+    parse::Event,
+    profile::{Profile, TouchKind},
+    trace::{format_frames, parse_trace},
+};
+
+// Both devices report the protocol timestamp (ms) as microseconds
+fn check_timestamps(frames: &[Vec<InputEvent>], timestamp: i32) -> Result<(), String> {
+    let expected = timestamp.wrapping_mul(1000);
+    let stamps = frames.iter().flatten().filter(|event| {
+        event.event_type() == EventType::MISC && event.code() == MiscCode::MSC_TIMESTAMP.0
+    });
+    for event in stamps {
+        if event.value() != expected {
+            return Err(format!(
+                "MSC_TIMESTAMP {} for the protocol timestamp {}",
+                event.value(),
+                timestamp
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn replay(messages: &[Vec<u8>]) -> Result<String, String> {
+    let profile = Profile::default();
+    let stylus_sink = CaptureSink::new();
+    let finger_sink = CaptureSink::new();
+    let mut stylus = StylusBackend::from_sink(Box::new(stylus_sink.clone()));
+    let mut finger = FingerBackend::from_sink(Box::new(finger_sink.clone()), TouchKind::Touchpad);
+
+    let mut init = None;
+    let mut output = String::new();
+    for (index, message) in messages.iter().enumerate() {
+        let mut buf = ByteReader::from_bytes(message);
+        buf.set_endian(Endian::LittleEndian);
+        let mut stylus_frames = Vec::new();
+        let mut finger_frames = Vec::new();
+        for event in Event::parse_message(&mut buf, &mut init) {
+            let error = |err: String| format!("Message {}: {}", index + 1, err);
+            let (event, _) = event.map_err(error)?;
+            let timestamp = match event {
+                Event::Stylus(stylus_data) => {
+                    stylus.process(&stylus_data, &profile.barrel)?;
+                    Some(stylus_data.timestamp)
+                }
+                Event::Finger(finger_data) => {
+                    finger.process(&[finger_data], None)?;
+                    None
+                }
+                Event::FingerFrame(frame) => {
+                    finger.process(&frame.fingers, Some(frame.timestamp))?;
+                    Some(frame.timestamp)
+                }
+                // Geometry and keys do not reach these backends
+                Event::Init(_) | Event::Button(_) | Event::Key(_) => None,
+            };
+
+            let (stylus_new, finger_new) = (stylus_sink.take(), finger_sink.take());
+            if let Some(timestamp) = timestamp {
+                check_timestamps(&stylus_new, timestamp).map_err(error)?;
+                check_timestamps(&finger_new, timestamp).map_err(error)?;
+            }
+            stylus_frames.extend(stylus_new);
+            finger_frames.extend(finger_new);
+        }
+        output.push_str(&format_frames("stylus", &stylus_frames));
+        output.push_str(&format_frames("finger", &finger_frames));
+    }
+
+    stylus.reset()?;
+    finger.reset()?;
+    output.push_str("# reset\n");
+    output.push_str(&format_frames("stylus", &stylus_sink.take()));
+    output.push_str(&format_frames("finger", &finger_sink.take()));
+    Ok(output)
+}
+
+// Line number and both lines of the first difference
+fn first_difference(expected: &str, actual: &str) -> String {
+    let mut expected_lines = expected.lines();
+    let mut actual_lines = actual.lines();
+    let mut line = 1;
+    loop {
+        match (expected_lines.next(), actual_lines.next()) {
+            (Some(expected), Some(actual)) if expected == actual => line += 1,
+            (expected, actual) => {
+                return format!(
+                    "line {}: expected {:?}, got {:?}",
+                    line,
+                    expected.unwrap_or("<end>"),
+                    actual.unwrap_or("<end>")
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn golden_traces() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/traces");
+    let bless = std::env::var_os("PENDROID_BLESS").is_some();
+
+    let mut traces = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "trace"))
+        .collect::<Vec<_>>();
+    traces.sort();
+    assert!(!traces.is_empty(), "No trace in {}", dir.display());
+
+    let mut failures = Vec::new();
+    for trace in traces {
+        let name = trace.file_stem().unwrap().to_string_lossy().to_string();
+        let messages = parse_trace(&fs::read_to_string(&trace).unwrap())
+            .unwrap_or_else(|err| panic!("{}: {}", name, err));
+        let actual = replay(&messages).unwrap_or_else(|err| panic!("{}: {}", name, err));
+
+        let expected_path = trace.with_extension("expected");
+        if bless {
+            fs::write(&expected_path, &actual).unwrap();
+            continue;
+        }
+        match fs::read_to_string(&expected_path) {
+            Ok(expected) if expected == actual => {}
+            Ok(expected) => failures.push(format!(
+                "{}: {}",
+                name,
+                first_difference(&expected, &actual)
+            )),
+            Err(err) => failures.push(format!("{}: {}", name, err)),
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "Output changed, rerun with PENDROID_BLESS=1 if intended:\n{}",
+        failures.join("\n")
+    );
+}